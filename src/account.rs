@@ -0,0 +1,225 @@
+use crate::error::Error;
+use crate::session::Session;
+use futures::lock::Mutex;
+use hyper::body::{Buf, Bytes};
+use hyper::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// The account owner's identity, as reported by `accountLogin`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DsInfo {
+    #[serde(default)]
+    pub dsid: String,
+    #[serde(default)]
+    pub apple_id: String,
+    #[serde(default)]
+    pub apple_id_aliases: Vec<String>,
+    #[serde(default)]
+    pub full_name: String,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
+    #[serde(default)]
+    pub primary_email: Option<String>,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub language_code: Option<String>,
+    #[serde(default)]
+    pub country_code: Option<String>,
+    #[serde(default)]
+    pub hsa_version: Option<u32>,
+}
+
+// Storage used by one kind of media, e.g. photos or documents.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaUsage {
+    pub media_key: String,
+    #[serde(default)]
+    pub display_label: Option<String>,
+    #[serde(default)]
+    pub display_color: Option<String>,
+    pub usage_in_bytes: u64,
+}
+
+// The account's storage quota.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageQuota {
+    #[serde(default)]
+    pub used_storage_in_bytes: u64,
+    #[serde(default)]
+    pub total_storage_in_bytes: u64,
+    #[serde(default)]
+    pub comp_storage_in_bytes: u64,
+    #[serde(default)]
+    pub commerce_storage_in_bytes: u64,
+}
+
+impl StorageQuota {
+    pub fn available_in_bytes(&self) -> u64 {
+        self.total_storage_in_bytes
+            .saturating_sub(self.used_storage_in_bytes)
+    }
+}
+
+// Whether the account is at or near its quota.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaStatus {
+    #[serde(default)]
+    pub over_quota: bool,
+    #[serde(default, rename = "almost-full")]
+    pub almost_full: bool,
+    #[serde(default)]
+    pub have_max_quota_tier: bool,
+    #[serde(default)]
+    pub paid_quota: bool,
+}
+
+// Storage used by one member of a family sharing plan.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FamilyMemberUsage {
+    #[serde(default)]
+    pub dsid: Option<u64>,
+    #[serde(default)]
+    pub full_name: Option<String>,
+    #[serde(default)]
+    pub apple_id: Option<String>,
+    #[serde(default)]
+    pub usage_in_bytes: u64,
+}
+
+// Storage used by a family sharing plan.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FamilyUsage {
+    #[serde(default)]
+    pub usage_in_bytes: u64,
+    #[serde(default)]
+    pub family_members: Vec<FamilyMemberUsage>,
+}
+
+// The response of `storageUsageInfo`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageUsage {
+    #[serde(default)]
+    pub storage_usage_by_media: Vec<MediaUsage>,
+    #[serde(default, rename = "storageUsageInfo")]
+    pub quota: StorageQuota,
+    #[serde(default)]
+    pub quota_status: QuotaStatus,
+    #[serde(default, rename = "familyStorageUsageInfo")]
+    pub family: Option<FamilyUsage>,
+}
+
+// A device registered to the account.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    #[serde(default)]
+    pub udid: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub model_display_name: Option<String>,
+    #[serde(default)]
+    pub os_version: Option<String>,
+    #[serde(default)]
+    pub serial_number: Option<String>,
+}
+
+// Everything known about the account: owner identity, storage and devices.
+#[derive(Clone, Debug)]
+pub struct AccountInfo {
+    pub ds_info: DsInfo,
+    pub storage: StorageUsage,
+    pub devices: Vec<Device>,
+}
+
+pub struct AccountService {
+    session: Arc<Mutex<Session>>,
+    url: String,
+}
+
+impl AccountService {
+    // Constructs an interface to the account service at `url`.
+    pub fn new(session: Arc<Mutex<Session>>, url: String) -> AccountService {
+        AccountService { session, url }
+    }
+
+    // Retrieves the owner identity stored by the last `accountLogin`.
+    pub async fn ds_info(&self) -> Result<DsInfo, Error> {
+        let session = self.session.lock().await;
+        session
+            .data()
+            .ds_info()
+            .cloned()
+            .ok_or_else(|| Error::MissingCacheItem(String::from("dsInfo")))
+    }
+
+    // Retrieves storage usage broken down by media type and family member.
+    pub async fn storage(&self) -> Result<StorageUsage, Error> {
+        let mut session = self.session.lock().await;
+        let uri = format!("{}/storageUsageInfo", session.endpoints().setup);
+
+        let response = session
+            .request(Method::POST, uri, Bytes::new(), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        if response.status() == StatusCode::OK {
+            let body = hyper::body::aggregate(response).await?;
+            Ok(serde_json::from_reader(body.reader())?)
+        } else {
+            Err(Error::UnexpectedStatus(response.status()))
+        }
+    }
+
+    // Retrieves the devices registered to the account.
+    pub async fn devices(&self) -> Result<Vec<Device>, Error> {
+        let uri = format!("{}/setup/web/device/getDevices", self.url);
+
+        let mut session = self.session.lock().await;
+        let response = session
+            .request(Method::GET, uri, Bytes::new(), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        if response.status() == StatusCode::OK {
+            let body = hyper::body::aggregate(response).await?;
+            let devices: serde_json::Value = serde_json::from_reader(body.reader())?;
+            match devices.get("devices") {
+                Some(devices) => Ok(serde_json::from_value(devices.clone())?),
+                None => Ok(Vec::new()),
+            }
+        } else {
+            Err(Error::UnexpectedStatus(response.status()))
+        }
+    }
+
+    // Retrieves the owner identity, storage usage and devices together.
+    pub async fn info(&self) -> Result<AccountInfo, Error> {
+        Ok(AccountInfo {
+            ds_info: self.ds_info().await?,
+            storage: self.storage().await?,
+            devices: self.devices().await?,
+        })
+    }
+}
@@ -0,0 +1,355 @@
+use crate::drive::{DriveNode, DriveService, File, WalkOptions};
+use crate::error::Error;
+use crate::store::{load_json, save_json, SAVE_INTERVAL};
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+// The directory in the backup holding its manifest and partial downloads.
+static STATE_DIR: &str = ".icloud-backup";
+static MANIFEST: &str = "manifest.json";
+static PARTIAL_DIR: &str = "partial";
+
+// What happens to the local copy of an item deleted from the drive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeletionPolicy {
+    Keep,
+    // Moves the local copy into this directory, beneath a folder named for
+    // the time of the run.
+    Archive(PathBuf),
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ManifestEntry {
+    etag: Option<String>,
+    // Relative to the backup root.
+    path: PathBuf,
+    folder: bool,
+    // The size of the local copy, and the date the file was modified on
+    // the drive, which is also its mtime.
+    size: u64,
+    modified: Option<DateTime<FixedOffset>>,
+}
+
+// What the backup holds, keyed by drivewsid.
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    items: BTreeMap<String, ManifestEntry>,
+    completed: Option<DateTime<Utc>>,
+}
+
+// The outcome of a backup run.
+#[derive(Default, Debug)]
+pub struct BackupReport {
+    pub downloaded: Vec<PathBuf>,
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    pub unchanged: usize,
+    // Local copies of deleted items that were archived or deleted.
+    pub removed: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, Error)>,
+    // Folders that could not be listed, and items that could not be read.
+    // Deletions are not applied when there are any, as the items they hide
+    // would look deleted.
+    pub listing_errors: Vec<Error>,
+}
+
+// A one-way mirror of iCloud Drive in a local directory.
+//
+// Each run downloads the files that are new or changed since the last one,
+// writing them to a temporary file first so an interrupted run never leaves
+// a partial file in place. Files that were only moved or renamed are moved
+// locally. A manifest of drivewsid, etag and path is saved as the run goes,
+// so a run picks up where an interrupted one stopped.
+pub struct Backup {
+    root: PathBuf,
+    deletions: DeletionPolicy,
+}
+
+fn manifest_path(root: &Path) -> PathBuf {
+    root.join(STATE_DIR).join(MANIFEST)
+}
+
+// Whether a path stays within the backup root and out of its state.
+fn is_safe(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_)))
+        && !path.starts_with(STATE_DIR)
+}
+
+fn mtime(date: &DateTime<FixedOffset>) -> SystemTime {
+    SystemTime::from(*date)
+}
+
+// Whether a local file looks like the copy described, comparing its size
+// and its mtime to the second.
+fn matches(path: &Path, size: u64, modified: &DateTime<FixedOffset>) -> bool {
+    let seconds = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .ok()
+    };
+    std::fs::metadata(path).is_ok_and(|metadata| {
+        metadata.is_file()
+            && metadata.len() == size
+            && metadata.modified().ok().and_then(seconds) == seconds(mtime(modified))
+    })
+}
+
+fn partial_name(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// Moves a file, copying it when it lives on another filesystem.
+pub(crate) fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+// Downloads a file into a temporary file in `partial` and renames it to
+// `target` once complete, with the file's modification date as its mtime.
+// Returns the size written.
+pub(crate) async fn download_file(
+    drive: &mut DriveService,
+    file: &File,
+    partial: &Path,
+    target: &Path,
+) -> Result<u64, Error> {
+    let temp = partial.join(partial_name(&file.id));
+    let mut download = drive.download(file).await?;
+    let mut writer = tokio::fs::File::create(&temp).await?;
+    let mut written = 0;
+    while let Some(chunk) = download.next().await {
+        let chunk = chunk?;
+        writer.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+    }
+    writer.flush().await?;
+    let writer = writer.into_std().await;
+    writer.sync_all()?;
+    writer.set_modified(mtime(&file.date_modified))?;
+    drop(writer);
+    move_file(&temp, target)?;
+    Ok(written)
+}
+
+impl Backup {
+    // A backup into `root`, which is created if needed. Local copies of
+    // deleted items are kept unless a deletion policy says otherwise.
+    pub fn new<P: AsRef<Path>>(root: P) -> Backup {
+        Backup {
+            root: root.as_ref().to_path_buf(),
+            deletions: DeletionPolicy::Keep,
+        }
+    }
+
+    pub fn with_deletions(mut self, policy: DeletionPolicy) -> Backup {
+        self.deletions = policy;
+        self
+    }
+
+    // Brings the backup up to date with the drive.
+    pub async fn run(&self, drive: &mut DriveService) -> Result<BackupReport, Error> {
+        let started = Utc::now();
+        let partial = self.root.join(STATE_DIR).join(PARTIAL_DIR);
+        if partial.exists() {
+            std::fs::remove_dir_all(&partial)?;
+        }
+        std::fs::create_dir_all(&partial)?;
+
+        let mut manifest = load_json(&manifest_path(&self.root))?;
+        let mut report = BackupReport::default();
+        let mut seen = BTreeSet::new();
+        // Folders whose local directory is no longer used, once emptied.
+        let mut stale_dirs = Vec::new();
+        let mut unsaved = 0;
+
+        let root = drive.root().await?;
+        let mut walk = Box::pin(drive.walk(&root, WalkOptions::new()));
+        while let Some(item) = walk.next().await {
+            let (path, node) = match item {
+                Ok(item) => item,
+                Err(err) => {
+                    report.listing_errors.push(err);
+                    continue;
+                }
+            };
+            if !is_safe(&path) {
+                report.failed.push((
+                    path.clone(),
+                    Error::InvalidResponse(format!("Unsafe path {}", path.display())),
+                ));
+                continue;
+            }
+            match &node {
+                DriveNode::File(file) => {
+                    seen.insert(file.id.clone());
+                    match self.sync_file(drive, file, &path, &partial, &mut manifest, &mut report).await {
+                        Ok(true) => unsaved += 1,
+                        Ok(false) => {}
+                        Err(err) => report.failed.push((path, err)),
+                    }
+                }
+                DriveNode::Folder(_) | DriveNode::AppLibrary(_) => {
+                    seen.insert(node.id().clone());
+                    if let Err(err) = std::fs::create_dir_all(self.root.join(&path)) {
+                        report.failed.push((path, err.into()));
+                        continue;
+                    }
+                    let previous = manifest.items.insert(
+                        node.id().clone(),
+                        ManifestEntry {
+                            etag: node.etag().cloned(),
+                            path: path.clone(),
+                            folder: true,
+                            size: 0,
+                            modified: None,
+                        },
+                    );
+                    if let Some(previous) = previous.filter(|previous| previous.path != path) {
+                        stale_dirs.push(previous.path);
+                    }
+                }
+                DriveNode::Unknown(_) => {
+                    if let Some(err) = node.read_error() {
+                        report
+                            .listing_errors
+                            .push(Error::InvalidResponse(format!("Could not read {}: {}", path.display(), err)));
+                    }
+                }
+            }
+            if unsaved >= SAVE_INTERVAL {
+                save_json(&manifest_path(&self.root), &manifest)?;
+                unsaved = 0;
+            }
+        }
+
+        if report.listing_errors.is_empty() {
+            let deleted: Vec<String> = manifest
+                .items
+                .keys()
+                .filter(|id| !seen.contains(*id))
+                .cloned()
+                .collect();
+            // Paths now used by other items are left alone.
+            let in_use: BTreeSet<PathBuf> = manifest
+                .items
+                .iter()
+                .filter(|(id, _)| seen.contains(*id))
+                .map(|(_, entry)| entry.path.clone())
+                .collect();
+            for id in deleted {
+                let entry = match manifest.items.remove(&id) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                if in_use.contains(&entry.path) || self.deletions == DeletionPolicy::Keep {
+                    continue;
+                }
+                if entry.folder {
+                    stale_dirs.push(entry.path);
+                } else if let Err(err) = self.remove(&entry.path, started) {
+                    report.failed.push((entry.path, err));
+                } else {
+                    report.removed.push(entry.path);
+                }
+            }
+        }
+
+        // Deepest first, so a folder is emptied before its parent.
+        stale_dirs.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+        for dir in stale_dirs {
+            std::fs::remove_dir(self.root.join(dir)).ok();
+        }
+
+        manifest.completed = Some(started);
+        save_json(&manifest_path(&self.root), &manifest)?;
+        std::fs::remove_dir_all(&partial)?;
+        Ok(report)
+    }
+
+    // Brings the local copy of a file up to date, returning whether the
+    // manifest changed.
+    async fn sync_file(
+        &self,
+        drive: &mut DriveService,
+        file: &File,
+        path: &Path,
+        partial: &Path,
+        manifest: &mut Manifest,
+        report: &mut BackupReport,
+    ) -> Result<bool, Error> {
+        let target = self.root.join(path);
+        // The copy already made, if the contents haven't changed since;
+        // failing that, a copy left in place by an interrupted run.
+        let (local, size) = match manifest.items.get(&file.id) {
+            Some(entry)
+                if !entry.folder
+                    && (entry.etag == file.etag || entry.modified == Some(file.date_modified)) =>
+            {
+                (entry.path.clone(), entry.size)
+            }
+            _ => (path.to_path_buf(), file.size),
+        };
+
+        let entry = |size| ManifestEntry {
+            etag: file.etag.clone(),
+            path: path.to_path_buf(),
+            folder: false,
+            size,
+            modified: Some(file.date_modified),
+        };
+
+        if matches(&self.root.join(&local), size, &file.date_modified) {
+            if local != path {
+                move_file(&self.root.join(&local), &target)?;
+                report.moved.push((local, path.to_path_buf()));
+            } else {
+                report.unchanged += 1;
+            }
+            let previous = manifest.items.insert(file.id.clone(), entry(size));
+            return Ok(previous.is_none_or(|previous| previous.path != path || previous.etag != file.etag));
+        }
+
+        let written = download_file(drive, file, partial, &target).await?;
+        manifest.items.insert(file.id.clone(), entry(written));
+        report.downloaded.push(path.to_path_buf());
+        Ok(true)
+    }
+
+    // Applies the deletion policy to the local copy of a deleted file, then
+    // removes the directories it leaves empty.
+    fn remove(&self, path: &Path, started: DateTime<Utc>) -> Result<(), Error> {
+        let local = self.root.join(path);
+        if !local.exists() {
+            return Ok(());
+        }
+        match &self.deletions {
+            DeletionPolicy::Keep => return Ok(()),
+            DeletionPolicy::Archive(archive) => {
+                let run = started.format("%Y-%m-%dT%H-%M-%S").to_string();
+                move_file(&local, &archive.join(run).join(path))?;
+            }
+            DeletionPolicy::Delete => std::fs::remove_file(&local)?,
+        }
+        let mut dir = local.parent();
+        while let Some(parent) = dir.filter(|dir| *dir != self.root) {
+            if std::fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@ use crate::icloud::drive::DriveNode;
 use crate::icloud::error::Error;
 use crate::icloud::SessionData;
 use crate::icloud::Client;
+use crate::icloud::VerificationMode;
 
 async fn login_prompt() -> (String, String) {
     print!("Enter username: ");
@@ -26,7 +27,7 @@ async fn login_prompt() -> (String, String) {
     (username, password)
 }
 
-async fn prompt_2fa() -> String {
+async fn prompt_code() -> String {
     print!("Enter 2FA code: ");
     stdout().flush().unwrap();
     let mut code = String::new();
@@ -37,6 +38,43 @@ async fn prompt_2fa() -> String {
     code
 }
 
+async fn prompt_2fa(client: &mut Client) -> Result<(), Error> {
+    let phones = client.trusted_phone_numbers().await.unwrap_or_default();
+    let mut methods = Vec::new();
+    for phone in &phones {
+        methods.push((phone, VerificationMode::Sms));
+        methods.push((phone, VerificationMode::Voice));
+    }
+
+    println!("0: Code from a trusted device");
+    for (index, (phone, mode)) in methods.iter().enumerate() {
+        let method = match mode {
+            VerificationMode::Sms => "Text message",
+            VerificationMode::Voice => "Voice call",
+        };
+        println!("{}: {} to {}", index + 1, method, phone.obfuscated_number);
+    }
+    print!("Choose a 2FA method: ");
+    stdout().flush().unwrap();
+    let mut choice = String::new();
+    if let Err(msg) = stdin().read_line(&mut choice) {
+        panic!("{}", msg);
+    }
+
+    match choice.trim().parse::<usize>() {
+        Ok(index) if index > 0 && index <= methods.len() => {
+            let (phone, mode) = methods[index - 1];
+            client.request_phone_code(phone, mode).await?;
+            let code = prompt_code().await;
+            client.authenticate_phone_2fa(phone, mode, code.as_str()).await
+        }
+        _ => {
+            let code = prompt_code().await;
+            client.authenticate_2fa(code.as_str()).await
+        }
+    }
+}
+
 async fn authenticate(client: &mut Client) -> Result<(), Error> {
     match client.authenticate().await {
         Err(Error::AuthenticationFailed(_)) | Err(Error::MissingCacheItem(_)) => {
@@ -44,20 +82,12 @@ async fn authenticate(client: &mut Client) -> Result<(), Error> {
             match client.login(username.as_str(), password.as_str()).await {
                 Ok(()) => Ok(()),
                 Err(err) => match err {
-                    Error::Needs2FA => {
-                        let code = prompt_2fa().await;
-                        client.authenticate_2fa(code.as_str()).await?;
-                        Ok(())
-                    }
+                    Error::Needs2FA => prompt_2fa(client).await,
                     _ => Err(err),
                 },
             }
         }
-        Err(Error::Needs2FA) => {
-            let code = prompt_2fa().await;
-            client.authenticate_2fa(code.as_str()).await?;
-            Ok(())
-        }
+        Err(Error::Needs2FA) => prompt_2fa(client).await,
         Err(err) => {
             println!("{}", err);
             Err(err)
@@ -80,19 +110,13 @@ pub async fn main() -> Result<(), Error> {
     if let Ok(mut client) = Client::new(session_data) {
         authenticate(&mut client).await?;
 
-        if let Some(mut drive) = client.drive().await {
-            let root = drive.root().await?;
-            for item in root.iter() {
-                let item = drive.get_node(item.id()).await?;
-                println!("{}", item);
-                match item {
-                    DriveNode::Folder(folder) => {
-                        for item in folder.iter() {
-                            println!("{}", item);
-                        }
-                    }, _ => {
-
-                    }
+        let mut drive = client.drive().await?;
+        let root = drive.root().await?;
+        for item in drive.children(&root).await? {
+            println!("{}", item);
+            if let DriveNode::Folder(folder) = item {
+                for item in drive.children(&folder).await? {
+                    println!("{}", item);
                 }
             }
         }
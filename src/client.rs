@@ -1,6 +1,13 @@
+use crate::account::{AccountInfo, AccountService};
 use crate::drive::DriveService;
+use crate::endpoint::Endpoints;
 use crate::error::Error;
-use crate::session::{Session, SessionData};
+use crate::transport::{HyperTransport, Transport};
+use crate::session::{
+    Service, ServiceInfo, Session, SessionData, SessionStatus, TrustedPhoneNumber,
+    VerificationMode,
+};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use futures::lock::Mutex;
 
@@ -9,21 +16,118 @@ pub struct Client {
     session: Arc<Mutex<Session>>,
 }
 
-impl Client {
-    pub fn new(data: SessionData) -> Result<Client, Error> {
+// Configures the endpoints and transport used by a `Client`.
+pub struct ClientBuilder {
+    data: Option<SessionData>,
+    endpoints: Endpoints,
+    https_only: bool,
+    transport: Option<Arc<dyn Transport>>,
+}
+
+impl ClientBuilder {
+    pub fn new() -> ClientBuilder {
+        ClientBuilder {
+            data: None,
+            endpoints: Endpoints::global(),
+            https_only: true,
+            transport: None,
+        }
+    }
+
+    // Restores a previously saved session.
+    pub fn session_data(mut self, data: SessionData) -> ClientBuilder {
+        self.data = Some(data);
+        self
+    }
+
+    // Sets the endpoint profile, e.g. `Endpoints::china()`.
+    pub fn endpoints(mut self, endpoints: Endpoints) -> ClientBuilder {
+        self.endpoints = endpoints;
+        self
+    }
+
+    // Allows plain HTTP connections, which is only useful for local test
+    // servers.
+    pub fn https_only(mut self, https_only: bool) -> ClientBuilder {
+        self.https_only = https_only;
+        self
+    }
+
+    // Sends requests through a custom transport instead of the default
+    // hyper client, e.g. a `MockTransport` or `ReplayTransport`.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> ClientBuilder {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let data = match self.data {
+            Some(data) => data,
+            None => SessionData::new()?,
+        };
+        let https_only = self.https_only;
+        let transport = self
+            .transport
+            .unwrap_or_else(|| Arc::new(HyperTransport::new(https_only)));
         Ok(Client {
-            session: Arc::new(Mutex::new(Session::new(data)?)),
+            session: Arc::new(Mutex::new(Session::new(data, self.endpoints, transport)?)),
         })
     }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+}
+
+impl Client {
+    pub fn new(data: SessionData) -> Result<Client, Error> {
+        ClientBuilder::new().session_data(data).build()
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
 
     // Creates an interface to the iCloud Drive using the current
-    // session.
-    pub async fn drive(&mut self) -> Option<DriveService> {
+    // session, failing if the account has no active drive service.
+    pub async fn drive(&mut self) -> Result<DriveService, Error> {
+        let clone = self.session.clone();
+        let session = self.session.lock().await;
+        let documents = session.service(Service::Documents).ok().map(|s| s.url.clone());
+        let drive = DriveService::new(clone, session.service(Service::Drive)?.url.clone());
+        Ok(match documents {
+            Some(url) => drive.with_documents_url(url),
+            None => drive,
+        })
+    }
+
+    // Creates an interface to the account service using the current
+    // session, failing if the account has no active account service.
+    pub async fn account_service(&self) -> Result<AccountService, Error> {
         let clone = self.session.clone();
         let session = self.session.lock().await;
-        session
-            .get_service_info(String::from("drive"))
-            .map(|s| DriveService::new(clone, s.url.clone()))
+        let url = session.service(Service::Account)?.url.clone();
+        Ok(AccountService::new(clone, url))
+    }
+
+    // Retrieves the owner identity, storage usage and registered devices.
+    pub async fn account(&self) -> Result<AccountInfo, Error> {
+        self.account_service().await?.info().await
+    }
+
+    // Lists every web service advertised for the account, keyed by name.
+    pub async fn services(&self) -> BTreeMap<String, ServiceInfo> {
+        let session = self.session.lock().await;
+        session.services().clone()
+    }
+
+    // Looks up a web service, failing with `Error::ServiceUnavailable` if
+    // the account has it but it is not currently active.
+    pub async fn service(&self, service: Service) -> Result<ServiceInfo, Error> {
+        let session = self.session.lock().await;
+        session.service(service).cloned()
     }
 
     // Authenticates using the local session information.
@@ -44,6 +148,47 @@ impl Client {
         session.authenticate_2fa(code).await
     }
 
+    // Lists the phone numbers that can receive two-factor authentication
+    // codes.
+    pub async fn trusted_phone_numbers(&mut self) -> Result<Vec<TrustedPhoneNumber>, Error> {
+        let mut session = self.session.lock().await;
+        session.trusted_phone_numbers().await
+    }
+
+    // Sends a two-factor authentication code to a trusted phone number.
+    pub async fn request_phone_code(
+        &mut self,
+        phone: &TrustedPhoneNumber,
+        mode: VerificationMode,
+    ) -> Result<(), Error> {
+        let mut session = self.session.lock().await;
+        session.request_phone_code(phone, mode).await
+    }
+
+    // Authenticates with a code sent to a trusted phone number.
+    pub async fn authenticate_phone_2fa(
+        &mut self,
+        phone: &TrustedPhoneNumber,
+        mode: VerificationMode,
+        code: &str,
+    ) -> Result<(), Error> {
+        let mut session = self.session.lock().await;
+        session.authenticate_phone_2fa(phone, mode, code).await
+    }
+
+    // Checks whether the saved session is still live and whether it is
+    // waiting on two-factor authentication.
+    pub async fn validate(&mut self) -> Result<SessionStatus, Error> {
+        let mut session = self.session.lock().await;
+        session.validate().await
+    }
+
+    // Signs out and clears the tokens and cookies from the session data.
+    pub async fn logout(&mut self) -> Result<(), Error> {
+        let mut session = self.session.lock().await;
+        session.logout().await
+    }
+
     // Saves the session data for restoration later.
     pub async fn save(&mut self) -> Option<SessionData> {
         let session = self.session.lock().await;
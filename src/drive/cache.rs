@@ -0,0 +1,249 @@
+use super::retrieve_details;
+use crate::error::Error;
+use crate::session::Session;
+use crate::store::{load_json, save_json};
+use chrono::{DateTime, Utc};
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub(crate) type SharedCache = Arc<std::sync::Mutex<MetadataCache>>;
+
+// The status given to items missing from the cache while offline.
+pub(crate) static NOT_CACHED: &str = "NOT_CACHED";
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedNode {
+    etag: Option<String>,
+    fetched: DateTime<Utc>,
+    // The node as `retrieveItemDetailsInFolders` described it, with its
+    // children for folders.
+    details: Value,
+}
+
+// Drive metadata saved on disk between runs, keyed by drivewsid. Entries
+// are revalidated by etag before they are used, so only folders that
+// changed are listed again.
+#[derive(Serialize, Deserialize, Default)]
+pub struct MetadataCache {
+    nodes: BTreeMap<String, CachedNode>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    // Entries known to match the server during this run.
+    #[serde(skip)]
+    fresh: BTreeSet<String>,
+}
+
+fn etag(details: &Value) -> Option<String> {
+    details["etag"].as_str().map(String::from)
+}
+
+impl MetadataCache {
+    // Opens the cache stored at `path`, which is created by `save` if it
+    // doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MetadataCache, Error> {
+        let path = path.as_ref();
+        let mut cache: MetadataCache = load_json(path)?;
+        cache.path = Some(path.to_path_buf());
+        Ok(cache)
+    }
+
+    // A cache that is never saved.
+    pub fn in_memory() -> MetadataCache {
+        MetadataCache::default()
+    }
+
+    // Writes the cache to disk. The file is replaced atomically, so an
+    // interrupted save leaves the previous cache intact.
+    pub fn save(&self) -> Result<(), Error> {
+        match &self.path {
+            Some(path) => save_json(path, self),
+            None => Ok(()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.fresh.clear();
+    }
+
+    fn get(&self, id: &str) -> Option<&Value> {
+        self.nodes.get(id).map(|node| &node.details)
+    }
+
+    fn cached_etag(&self, id: &str) -> Option<&String> {
+        self.nodes.get(id).and_then(|node| node.etag.as_ref())
+    }
+
+    // Marks a node as current. A listing fetched just now also tells which
+    // cached children are current, and which are outdated and can be
+    // dropped without asking the server; a revalidated listing doesn't, as
+    // a folder's etag only follows its own children.
+    fn validate(&mut self, id: &str, listed: bool) {
+        self.fresh.insert(String::from(id));
+        if !listed {
+            return;
+        }
+        let children: Vec<(String, bool)> = self
+            .get(id)
+            .and_then(|details| details["items"].as_array())
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| {
+                        let child = item["drivewsid"].as_str()?;
+                        let cached = self.cached_etag(child)?;
+                        Some((String::from(child), item["etag"].as_str() == Some(cached.as_str())))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        for (child, current) in children {
+            if current {
+                self.fresh.insert(child);
+            } else {
+                self.nodes.remove(&child);
+                self.fresh.remove(&child);
+            }
+        }
+    }
+
+    fn store(&mut self, id: &str, details: &Value) {
+        // Refused items and shallow replies are not worth keeping.
+        if details.get("status").is_some() || details.get("type").is_none() {
+            self.nodes.remove(id);
+            self.fresh.remove(id);
+            return;
+        }
+        self.nodes.insert(
+            String::from(id),
+            CachedNode {
+                etag: etag(details),
+                fetched: Utc::now(),
+                details: details.clone(),
+            },
+        );
+        self.validate(id, true);
+    }
+
+    // Requires a node to be revalidated before its entry is used again.
+    pub(crate) fn expire(&mut self, id: &str) {
+        self.fresh.remove(id);
+    }
+
+    // Requires every entry to be revalidated before it is used again.
+    pub(crate) fn expire_all(&mut self) {
+        self.fresh.clear();
+    }
+
+    // Drops the entries for nodes that changed, and for the folders that
+    // list them. A node may be known only from its parent's listing, so the
+    // listings are searched as well as the node's own entry.
+    pub(crate) fn invalidate(&mut self, ids: &[String]) {
+        let changed: BTreeSet<&str> = ids.iter().map(String::as_str).collect();
+        let mut stale: BTreeSet<String> = ids.iter().cloned().collect();
+        for (id, node) in &self.nodes {
+            if changed.contains(id.as_str()) {
+                stale.extend(node.details["parentId"].as_str().map(String::from));
+            }
+            let lists_changed = node.details["items"].as_array().is_some_and(|items| {
+                items
+                    .iter()
+                    .any(|item| item["drivewsid"].as_str().is_some_and(|child| changed.contains(child)))
+            });
+            if lists_changed {
+                stale.insert(id.clone());
+            }
+        }
+        for id in stale {
+            self.nodes.remove(&id);
+            self.fresh.remove(&id);
+        }
+    }
+}
+
+// Retrieves the details of nodes like `retrieve_details`, reading through
+// the cache when there is one. Cached entries not yet known to be current
+// are revalidated together with one shallow request, and only those whose
+// etag changed are fetched in full. Offline, only the cache is consulted.
+pub(crate) async fn fetch_details(
+    session: &Mutex<Session>,
+    url: &str,
+    cache: Option<&SharedCache>,
+    offline: bool,
+    ids: &[String],
+) -> Result<Vec<Value>, Error> {
+    let cache = match cache {
+        Some(cache) => cache,
+        None if offline => {
+            return Ok(ids
+                .iter()
+                .map(|id| json!({ "drivewsid": id, "status": NOT_CACHED }))
+                .collect());
+        }
+        None => return retrieve_details(session, url, ids, false).await,
+    };
+
+    if offline {
+        let cache = cache.lock().map_err(|_| Error::MutexError)?;
+        return Ok(ids
+            .iter()
+            .map(|id| {
+                cache
+                    .get(id)
+                    .cloned()
+                    .unwrap_or_else(|| json!({ "drivewsid": id, "status": NOT_CACHED }))
+            })
+            .collect());
+    }
+
+    let (stale, missing): (Vec<String>, Vec<String>) = {
+        let cache = cache.lock().map_err(|_| Error::MutexError)?;
+        ids.iter()
+            .filter(|id| !cache.fresh.contains(*id))
+            .cloned()
+            .partition(|id| cache.nodes.contains_key(id))
+    };
+
+    let mut to_fetch = missing;
+    if !stale.is_empty() {
+        let current = retrieve_details(session, url, &stale, true).await?;
+        let mut cache = cache.lock().map_err(|_| Error::MutexError)?;
+        for (id, details) in stale.into_iter().zip(current.iter()) {
+            if etag(details).is_some() && cache.cached_etag(&id) == etag(details).as_ref() {
+                cache.validate(&id, false);
+            } else {
+                to_fetch.push(id);
+            }
+        }
+    }
+
+    let mut fetched = BTreeMap::new();
+    if !to_fetch.is_empty() {
+        let details = retrieve_details(session, url, &to_fetch, false).await?;
+        let mut cache = cache.lock().map_err(|_| Error::MutexError)?;
+        for (id, details) in to_fetch.into_iter().zip(details) {
+            cache.store(&id, &details);
+            fetched.insert(id, details);
+        }
+    }
+
+    let cache = cache.lock().map_err(|_| Error::MutexError)?;
+    ids.iter()
+        .map(|id| match fetched.get(id).or_else(|| cache.get(id)) {
+            Some(details) => Ok(details.clone()),
+            None => Err(Error::InvalidResponse(format!("Missing details of {}", id))),
+        })
+        .collect()
+}
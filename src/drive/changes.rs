@@ -0,0 +1,173 @@
+use super::{DriveNode, DriveService, WalkOptions, WalkOrder};
+use crate::error::Error;
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct KnownNode {
+    parent_id: Option<String>,
+    path: PathBuf,
+    // The size and modification date of a file, which change with its
+    // contents.
+    contents: Option<(u64, DateTime<FixedOffset>)>,
+}
+
+// Where `DriveService::changes_since` left off. It records the state of
+// the tree when it was issued, and can be saved with serde to resume after
+// a restart.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ChangeToken {
+    nodes: BTreeMap<String, KnownNode>,
+    issued: Option<DateTime<Utc>>,
+}
+
+impl ChangeToken {
+    // A token from before anything was seen, against which every item is
+    // reported as created.
+    pub fn new() -> ChangeToken {
+        ChangeToken::default()
+    }
+
+    pub fn issued(&self) -> Option<DateTime<Utc>> {
+        self.issued
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    // The contents of a file changed.
+    Modified,
+    Moved { from: PathBuf },
+    Renamed { from: String },
+    Deleted,
+}
+
+// A change to an item. An item that was both moved and renamed is
+// reported twice: first as `Moved`, from its full previous path, then as
+// `Renamed`, from its previous name. Replaying the changes in order gives
+// the item's current path either way.
+#[derive(Clone)]
+pub struct Change {
+    pub id: String,
+    pub kind: ChangeKind,
+    // The item's path relative to the root, or its last known path once
+    // deleted.
+    pub path: PathBuf,
+    // The item as it is now, except for deletions.
+    pub node: Option<DriveNode>,
+}
+
+fn name_of(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl DriveService {
+    // Reports the changes to the drive since `token` was issued, along with
+    // the token to pass next time. The iCloud web services don't expose
+    // the CloudDocs zone's change feed, so the tree is compared with the
+    // state recorded in the token. Listings are fetched in batches, and
+    // with a metadata cache only the folders whose etag changed are listed
+    // again.
+    pub async fn changes_since(&mut self, token: &ChangeToken) -> Result<(Vec<Change>, ChangeToken), Error> {
+        // Whatever was fetched earlier may have been changed elsewhere since.
+        self.expire_cache()?;
+        let issued = Utc::now();
+        let root = self.root().await?;
+        let items: Vec<(PathBuf, DriveNode)> = self
+            .walk(&root, WalkOptions::new().order(WalkOrder::BreadthFirst))
+            .try_collect()
+            .await?;
+
+        // Items that could not be read are carried over from the token as
+        // they were, along with everything beneath them, rather than
+        // reported as deleted.
+        let unreadable: Vec<PathBuf> = items
+            .iter()
+            .filter(|(_, node)| node.read_error().is_some())
+            .flat_map(|(path, node)| {
+                let known = token.nodes.get(node.id()).map(|known| known.path.clone());
+                std::iter::once(path.clone()).chain(known)
+            })
+            .collect();
+        let hidden = |path: &Path| unreadable.iter().any(|unreadable| path.starts_with(unreadable));
+
+        let mut changes = Vec::new();
+        let mut nodes: BTreeMap<String, KnownNode> = token
+            .nodes
+            .iter()
+            .filter(|(_, known)| hidden(&known.path))
+            .map(|(id, known)| (id.clone(), known.clone()))
+            .collect();
+        for (path, node) in items {
+            if hidden(&path) {
+                continue;
+            }
+            let known = KnownNode {
+                parent_id: node.parent_id().cloned(),
+                path: path.clone(),
+                contents: match &node {
+                    DriveNode::File(file) => Some((file.size, file.date_modified)),
+                    _ => None,
+                },
+            };
+            let mut change = |kind| {
+                changes.push(Change {
+                    id: node.id().clone(),
+                    kind,
+                    path: path.clone(),
+                    node: Some(node.clone()),
+                })
+            };
+            match token.nodes.get(node.id()) {
+                None => change(ChangeKind::Created),
+                Some(previous) => {
+                    if previous.parent_id != known.parent_id {
+                        change(ChangeKind::Moved {
+                            from: previous.path.clone(),
+                        });
+                    }
+                    if name_of(&previous.path) != name_of(&path) {
+                        change(ChangeKind::Renamed {
+                            from: name_of(&previous.path),
+                        });
+                    }
+                    // The etag also changes when an item is moved or renamed,
+                    // and for a folder whenever its children do.
+                    if previous.contents != known.contents {
+                        change(ChangeKind::Modified);
+                    }
+                }
+            }
+            nodes.insert(node.id().clone(), known);
+        }
+
+        // Deletions are reported deepest first, so a folder follows its
+        // contents.
+        let mut deleted: Vec<(&String, &KnownNode)> = token
+            .nodes
+            .iter()
+            .filter(|(id, _)| !nodes.contains_key(*id))
+            .collect();
+        deleted.sort_by_key(|(_, known)| std::cmp::Reverse(known.path.components().count()));
+        changes.extend(deleted.into_iter().map(|(id, known)| Change {
+            id: id.clone(),
+            kind: ChangeKind::Deleted,
+            path: known.path.clone(),
+            node: None,
+        }));
+
+        Ok((
+            changes,
+            ChangeToken {
+                nodes,
+                issued: Some(issued),
+            },
+        ))
+    }
+}
@@ -0,0 +1,96 @@
+use super::{DriveService, File};
+use crate::error::Error;
+use futures::{Stream, TryStreamExt};
+use hyper::body::{Buf, Bytes, HttpBody};
+use hyper::{Body, Method, StatusCode};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// The contents of a file being downloaded from iCloud Drive, streamed as
+// they arrive.
+pub struct Download {
+    body: Body,
+    // The length of the contents, when the server reports it.
+    pub size: Option<u64>,
+    // Whether the contents are a zip archive of a package (bundle) file
+    // rather than the file itself.
+    pub is_package: bool,
+}
+
+impl Download {
+    // Adapts the download into an `AsyncRead`.
+    pub fn into_async_read(self) -> impl futures::io::AsyncRead + Send + Unpin {
+        self.map_err(|err| std::io::Error::other(err.to_string()))
+            .into_async_read()
+    }
+}
+
+impl Stream for Download {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.body)
+            .poll_data(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(Error::from)))
+    }
+}
+
+impl DriveService {
+    // Downloads the contents of a file. Package files are delivered as a
+    // zip archive, which is reported by `Download::is_package`.
+    pub async fn download(&mut self, file: &File) -> Result<Download, Error> {
+        let uri = format!(
+            "{}/ws/{}/download/by_id?document_id={}",
+            self.documents_url()?,
+            file.zone,
+            file.docwsid
+        );
+
+        let mut session = self.session.lock().await;
+        let response = session
+            .request(Method::GET, uri, Bytes::new(), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let body = hyper::body::aggregate(response).await?;
+        let tokens: serde_json::Value = serde_json::from_reader(body.reader())?;
+        let (url, is_package) = match (
+            tokens["data_token"]["url"].as_str(),
+            tokens["package_token"]["url"].as_str(),
+        ) {
+            (Some(url), _) => (String::from(url), false),
+            (None, Some(url)) => (String::from(url), true),
+            (None, None) => {
+                return Err(Error::InvalidResponse(String::from("Missing download token")));
+            }
+        };
+
+        let response = session
+            .request(Method::GET, url, Bytes::new(), |_| Ok(()))
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let size = response
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok());
+
+        Ok(Download {
+            body: response.into_body(),
+            size,
+            is_package,
+        })
+    }
+}
@@ -0,0 +1,194 @@
+use super::{batch_items, item_results, parse_item, split_name, DriveNode, DriveService, File, Folder, BATCH_SIZE};
+use crate::error::Error;
+use rand::RngCore;
+use serde_json::{json, Value};
+
+fn client_id() -> String {
+    let mut bytes = [0; 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let id: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("TEMP-{}", id)
+}
+
+fn items(reply: &Value, key: &str) -> Result<Vec<DriveNode>, Error> {
+    reply[key]
+        .as_array()
+        .ok_or_else(|| Error::InvalidResponse(format!("Missing {}", key)))?
+        .iter()
+        .map(parse_item)
+        .collect()
+}
+
+fn single(mut nodes: Vec<DriveNode>) -> Result<DriveNode, Error> {
+    if nodes.len() == 1 {
+        Ok(nodes.remove(0))
+    } else {
+        Err(Error::InvalidResponse(format!("Expected 1 item, got {}", nodes.len())))
+    }
+}
+
+impl DriveService {
+    // Creates folders named `names` inside `parent`.
+    pub async fn create_folders(
+        &mut self,
+        parent: &Folder,
+        names: &[&str],
+    ) -> Result<Vec<DriveNode>, Error> {
+        let folders: Vec<Value> = names
+            .iter()
+            .map(|name| json!({ "clientId": client_id(), "name": name }))
+            .collect();
+        let reply = self
+            .post(
+                "createFolders",
+                json!({
+                    "destinationDrivewsId": parent.id,
+                    "folders": folders,
+                }),
+            )
+            .await?;
+        items(&reply, "folders")
+    }
+
+    // Renames a node. For files, the extension is taken from `new_name`.
+    pub async fn rename(&mut self, node: &DriveNode, new_name: &str) -> Result<DriveNode, Error> {
+        let mut item = json!({
+            "drivewsid": node.id(),
+            "etag": node.etag().cloned().unwrap_or_default(),
+        });
+        match node {
+            DriveNode::File(_) => {
+                let (name, extension) = split_name(new_name);
+                item["name"] = json!(name);
+                item["extension"] = json!(extension.unwrap_or_default());
+            }
+            DriveNode::Folder(_) | DriveNode::AppLibrary(_) | DriveNode::Unknown(_) => {
+                item["name"] = json!(new_name);
+            }
+        }
+        let reply = self.post("renameItems", json!({ "items": [item] })).await?;
+        single(items(&reply, "items")?)
+    }
+
+    // Moves a node to Recently Deleted.
+    pub async fn delete(&mut self, node: &DriveNode) -> Result<DriveNode, Error> {
+        let reply = self
+            .post(
+                "moveItemsToTrash",
+                json!({
+                    "items": [{
+                        "drivewsid": node.id(),
+                        "etag": node.etag().cloned().unwrap_or_default(),
+                        "clientId": node.id(),
+                    }]
+                }),
+            )
+            .await?;
+        single(items(&reply, "items")?)
+    }
+
+    // Moves nodes into `destination`, in batches. The result for each node
+    // is reported separately, in the order given.
+    pub async fn move_items(
+        &mut self,
+        nodes: &[&DriveNode],
+        destination: &Folder,
+    ) -> Result<Vec<Result<DriveNode, Error>>, Error> {
+        let mut results = Vec::with_capacity(nodes.len());
+        for batch in nodes.chunks(BATCH_SIZE) {
+            let reply = self
+                .post(
+                    "moveItems",
+                    json!({
+                        "destinationDrivewsId": destination.id,
+                        "items": batch_items(batch),
+                    }),
+                )
+                .await?;
+            results.extend(item_results(&reply, batch.len())?);
+        }
+        Ok(results)
+    }
+
+    // Copies a file into `destination`.
+    pub async fn copy(&mut self, file: &File, destination: &Folder) -> Result<File, Error> {
+        self.copy_items(&[file], destination).await?.remove(0)
+    }
+
+    // Copies files into `destination`, in batches. The copy is made by the
+    // server when it supports `copyItems`; otherwise each file is
+    // downloaded and uploaded again. The result for each file is reported
+    // separately, in the order given.
+    pub async fn copy_items(
+        &mut self,
+        files: &[&File],
+        destination: &Folder,
+    ) -> Result<Vec<Result<File, Error>>, Error> {
+        let mut results = Vec::with_capacity(files.len());
+        for batch in files.chunks(BATCH_SIZE) {
+            let nodes: Vec<DriveNode> = batch
+                .iter()
+                .map(|file| DriveNode::File((*file).clone()))
+                .collect();
+            let nodes: Vec<&DriveNode> = nodes.iter().collect();
+            let reply = self
+                .post(
+                    "copyItems",
+                    json!({
+                        "destinationDrivewsId": destination.id,
+                        "items": batch_items(&nodes),
+                    }),
+                )
+                .await;
+            match reply {
+                Ok(reply) => {
+                    let copies = item_results(&reply, batch.len())?;
+                    results.extend(copies.into_iter().map(|node| match node? {
+                        DriveNode::File(file) => Ok(file),
+                        _ => Err(Error::InvalidDriveNodeType),
+                    }));
+                }
+                Err(Error::UnexpectedStatus(status))
+                    if status == hyper::StatusCode::NOT_FOUND
+                        || status == hyper::StatusCode::METHOD_NOT_ALLOWED
+                        || status == hyper::StatusCode::NOT_IMPLEMENTED =>
+                {
+                    for file in batch {
+                        results.push(self.copy_by_transfer(file, destination).await);
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(results)
+    }
+
+    // Copies a file by downloading it and uploading the contents again. The
+    // copy never replaces an item in `destination`: when the file's name is
+    // taken, as it is in the file's own folder, a " copy" suffix is added,
+    // then " copy 2" and so on.
+    async fn copy_by_transfer(&mut self, file: &File, destination: &Folder) -> Result<File, Error> {
+        let folder = self
+            .get_node(&destination.id)
+            .await?
+            .into_folder()
+            .ok_or(Error::InvalidDriveNodeType)?;
+        let with_suffix = |suffix: String| match &file.extension {
+            Some(extension) if !extension.is_empty() => format!("{}{}.{}", file.name, suffix, extension),
+            _ => format!("{}{}", file.name, suffix),
+        };
+        let mut name = file.full_name();
+        let mut count = 1;
+        while self.find_child(&folder, &name).is_some() {
+            name = match count {
+                1 => with_suffix(String::from(" copy")),
+                _ => with_suffix(format!(" copy {}", count)),
+            };
+            count += 1;
+        }
+        let download = self.download(file).await?;
+        let size = download.size.unwrap_or(file.size);
+        self.upload(destination, &name, download.into_async_read(), size)
+            .await
+    }
+}
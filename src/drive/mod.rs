@@ -0,0 +1,844 @@
+use std::sync::Arc;
+use futures::lock::Mutex;
+use crate::error::Error;
+use crate::session::Session;
+use chrono::{DateTime, FixedOffset};
+use hyper::body::Buf;
+use hyper::body::Bytes;
+use hyper::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::value::Value;
+
+mod cache;
+mod changes;
+mod download;
+mod edit;
+mod path;
+mod trash;
+mod upload;
+mod walk;
+
+pub use cache::MetadataCache;
+pub use changes::{Change, ChangeKind, ChangeToken};
+pub use download::Download;
+pub use trash::TrashItem;
+pub use walk::{WalkFilter, WalkOptions, WalkOrder};
+
+// The CloudKit zone a shared item belongs to.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ZoneId {
+    pub zone_name: String,
+    #[serde(default)]
+    pub owner_record_name: Option<String>,
+    #[serde(default)]
+    pub zone_type: Option<String>,
+}
+
+// The CloudKit share of a shared item.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareId {
+    pub record_name: String,
+    #[serde(default, rename = "zoneID")]
+    pub zone_id: Option<ZoneId>,
+}
+
+// A file stored in iCloud Drive.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct File {
+    #[serde(rename = "drivewsid")]
+    pub id: String,
+    #[serde(default)]
+    pub docwsid: String,
+    #[serde(default)]
+    pub zone: String,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub extension: Option<String>,
+    pub size: u64,
+    pub date_created: DateTime<FixedOffset>,
+    pub date_changed: DateTime<FixedOffset>,
+    pub date_modified: DateTime<FixedOffset>,
+    #[serde(default, rename = "lastOpenTime")]
+    pub last_opened: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub is_chained_to_parent: bool,
+    #[serde(default, rename = "shareID")]
+    pub share_id: Option<ShareId>,
+}
+
+impl File {
+    // The file name including its extension, which iCloud stores
+    // separately.
+    pub fn full_name(&self) -> String {
+        match &self.extension {
+            Some(extension) if !extension.is_empty() => format!("{}.{}", self.name, extension),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+// Whether a folder's `items` hold all of its children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LoadState {
+    // The children were not listed, e.g. for a folder that is itself a
+    // child in a listing. `items` is empty or incomplete.
+    #[default]
+    Partial,
+    Complete,
+}
+
+// A directory in iCloud Drive.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Folder {
+    #[serde(rename = "drivewsid")]
+    pub id: String,
+    #[serde(default)]
+    pub docwsid: String,
+    #[serde(default)]
+    pub zone: String,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    pub name: String,
+    pub date_created: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(default)]
+    pub is_chained_to_parent: bool,
+    #[serde(default, rename = "shareID")]
+    pub share_id: Option<ShareId>,
+    #[serde(default)]
+    pub file_count: Option<u64>,
+    #[serde(default)]
+    pub direct_children_count: Option<u64>,
+    #[serde(default)]
+    pub number_of_items: Option<u64>,
+    // Parsed separately, since each item may be of any type.
+    #[serde(skip)]
+    pub items: Vec<DriveNode>,
+    #[serde(skip)]
+    pub state: LoadState,
+}
+
+// An app's container, e.g. Pages or a third-party app, listed in the root
+// of iCloud Drive. Its contents live in the app's own zone.
+#[derive(Clone)]
+pub struct AppLibrary {
+    // The container as a folder, with the display name as its name.
+    pub folder: Folder,
+    pub bundle_id: String,
+    pub supported_extensions: Vec<String>,
+    pub supported_types: Vec<String>,
+}
+
+impl AppLibrary {
+    pub fn zone(&self) -> &str {
+        &self.folder.zone
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.folder.name
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppLibraryDetails {
+    #[serde(default)]
+    supported_extensions: Vec<String>,
+    #[serde(default)]
+    supported_types: Vec<String>,
+}
+
+// An item of a type this crate does not know, kept as the server sent it.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownNode {
+    #[serde(rename = "drivewsid")]
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub date_created: Option<DateTime<FixedOffset>>,
+    #[serde(skip)]
+    pub raw: Value,
+    // Why an item of a known type could not be read, when it is kept here
+    // so that the rest of its folder can be.
+    #[serde(skip)]
+    pub error: Option<String>,
+}
+
+impl UnknownNode {
+    // Keeps an item that failed to parse, with whatever could be made out
+    // of it.
+    fn malformed(value: &Value, err: Error) -> UnknownNode {
+        let string = |name: &str| value.get(name).and_then(Value::as_str).map(String::from);
+        UnknownNode {
+            id: string("drivewsid").unwrap_or_default(),
+            kind: string("type").unwrap_or_default(),
+            name: string("name").unwrap_or_default(),
+            etag: string("etag"),
+            parent_id: string("parentId"),
+            date_created: None,
+            raw: value.clone(),
+            error: Some(err.to_string()),
+        }
+    }
+}
+
+pub struct FolderIter<'a> {
+    current: std::slice::Iter<'a, DriveNode>,
+    state: LoadState,
+}
+
+impl FolderIter<'_> {
+    // Whether the iteration covers every child of the folder.
+    pub fn state(&self) -> LoadState {
+        self.state
+    }
+}
+
+impl<'a> Iterator for FolderIter<'a> {
+
+    type Item = &'a DriveNode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.current.next()
+    }
+
+}
+
+impl Folder {
+
+    pub fn iter(&self) -> FolderIter<'_> {
+        FolderIter{
+            current: self.items.iter(),
+            state: self.state,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.state == LoadState::Complete
+    }
+
+}
+
+// Extracts a part of a drivewsid, which has the form `TYPE::zone::docwsid`.
+fn id_part(drivewsid: &str, index: usize) -> String {
+    drivewsid
+        .splitn(3, "::")
+        .nth(index)
+        .map_or_else(String::new, String::from)
+}
+
+// Fills in a docwsid and zone the server left out from the drivewsid.
+fn fill_ids(drivewsid: &str, docwsid: &mut String, zone: &mut String) {
+    if docwsid.is_empty() {
+        *docwsid = id_part(drivewsid, 2);
+    }
+    if zone.is_empty() {
+        *zone = id_part(drivewsid, 1);
+    }
+}
+
+// Splits a file name into the `name` and `extension` fields iCloud stores.
+fn split_name(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            (stem, Some(extension))
+        }
+        _ => (name, None),
+    }
+}
+
+// The most items sent in a single batch request.
+const BATCH_SIZE: usize = 100;
+
+// Checks the `status` an item in a drivews mutation reply carries when the
+// change to that item was refused.
+fn parse_status(item: &Value) -> Result<(), Error> {
+    let id = item["drivewsid"].as_str().unwrap_or_default();
+    match item["status"].as_str() {
+        None | Some("OK") => Ok(()),
+        Some("ETAG_CONFLICT") => Err(Error::Conflict(String::from(id))),
+        Some("ID_INVALID") | Some("NOT_FOUND") => Err(Error::NotFound(String::from(id))),
+        Some(status) if status == cache::NOT_CACHED => Err(Error::Offline(String::from(id))),
+        Some(status) => Err(Error::InvalidResponse(format!("{}: {}", id, status))),
+    }
+}
+
+// Parses an item from a drivews mutation reply.
+fn parse_item(item: &Value) -> Result<DriveNode, Error> {
+    parse_status(item)?;
+    DriveNode::new(item)
+}
+
+// The `items` of a batch reply, which has one entry per item sent.
+fn reply_items(reply: &Value, expected: usize) -> Result<&Vec<Value>, Error> {
+    let items = reply["items"]
+        .as_array()
+        .ok_or_else(|| Error::InvalidResponse(String::from("Missing items")))?;
+    if items.len() != expected {
+        return Err(Error::InvalidResponse(format!(
+            "Expected {} items, got {}",
+            expected,
+            items.len()
+        )));
+    }
+    Ok(items)
+}
+
+// Parses each item of a batch reply separately, so one refused item does
+// not fail the others.
+fn item_results(reply: &Value, expected: usize) -> Result<Vec<Result<DriveNode, Error>>, Error> {
+    Ok(reply_items(reply, expected)?.iter().map(parse_item).collect())
+}
+
+fn batch_items(nodes: &[&DriveNode]) -> Vec<Value> {
+    nodes
+        .iter()
+        .map(|node| {
+            json!({
+                "drivewsid": node.id(),
+                "etag": node.etag().cloned().unwrap_or_default(),
+                "clientId": node.id(),
+            })
+        })
+        .collect()
+}
+
+// The path of a field within an item located at `prefix`.
+fn field_path(prefix: &str, name: &str) -> String {
+    match prefix {
+        "" => String::from(name),
+        prefix => format!("{}.{}", prefix, name),
+    }
+}
+
+// Deserializes an item, naming the offending field when it is malformed.
+// `prefix` locates the item within the reply, e.g. `items[3]`.
+fn parse<T: DeserializeOwned>(value: &Value, prefix: &str) -> Result<T, Error> {
+    serde_path_to_error::deserialize(value).map_err(|err| {
+        let field = match err.path().to_string().as_str() {
+            "." => String::from(prefix),
+            field => field_path(prefix, field),
+        };
+        Error::InvalidField(field, err.into_inner().to_string())
+    })
+}
+
+// A node within the iCloud Drive filesystem.
+#[derive(Clone)]
+pub enum DriveNode {
+    Folder(Folder),
+    File(File),
+    AppLibrary(AppLibrary),
+    Unknown(UnknownNode),
+}
+
+impl DriveNode {
+    pub(crate) fn new(value: &Value) -> Result<DriveNode, Error> {
+        DriveNode::parse(value, "")
+    }
+
+    fn parse(value: &Value, prefix: &str) -> Result<DriveNode, Error> {
+        let field = |name: &str| field_path(prefix, name);
+        let kind = match value.get("type") {
+            Some(Value::String(kind)) => kind.as_str(),
+            Some(_) => return Err(Error::InvalidField(field("type"), String::from("expected a string"))),
+            None => {
+                return Err(Error::InvalidField(String::from(prefix), String::from("missing field `type`")));
+            }
+        };
+
+        match kind {
+            "FOLDER" => Ok(DriveNode::Folder(DriveNode::parse_folder(value, prefix)?)),
+            "APP_LIBRARY" => {
+                let folder = DriveNode::parse_folder(value, prefix)?;
+                let details: AppLibraryDetails = parse(value, prefix)?;
+                // Third-party containers are named after the app's bundle
+                // id with an `iCloud.` prefix.
+                let bundle_id = folder.zone.strip_prefix("iCloud.").unwrap_or(&folder.zone);
+                Ok(DriveNode::AppLibrary(AppLibrary {
+                    bundle_id: String::from(bundle_id),
+                    folder,
+                    supported_extensions: details.supported_extensions,
+                    supported_types: details.supported_types,
+                }))
+            }
+            "FILE" => {
+                let mut file: File = parse(value, prefix)?;
+                fill_ids(&file.id, &mut file.docwsid, &mut file.zone);
+                Ok(DriveNode::File(file))
+            }
+            _ => {
+                let mut node: UnknownNode = parse(value, prefix)?;
+                node.raw = value.clone();
+                Ok(DriveNode::Unknown(node))
+            }
+        }
+    }
+
+    fn parse_folder(value: &Value, prefix: &str) -> Result<Folder, Error> {
+        let field = |name: &str| field_path(prefix, name);
+        let mut folder: Folder = parse(value, prefix)?;
+        fill_ids(&folder.id, &mut folder.docwsid, &mut folder.zone);
+        if let Some(items) = value.get("items") {
+            let items = items
+                .as_array()
+                .ok_or_else(|| Error::InvalidField(field("items"), String::from("expected an array")))?;
+            // A child that can't be read doesn't stop the rest of the
+            // listing from being read.
+            folder.items = items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    DriveNode::parse(item, &format!("{}[{}]", field("items"), index))
+                        .unwrap_or_else(|err| DriveNode::Unknown(UnknownNode::malformed(item, err)))
+                })
+                .collect();
+            // A listing shorter than the reported count was cut short.
+            if folder
+                .direct_children_count
+                .is_none_or(|count| count as usize <= folder.items.len())
+            {
+                folder.state = LoadState::Complete;
+            }
+        }
+        Ok(folder)
+    }
+
+    // The node as a folder, for folders and app libraries.
+    pub fn as_folder(&self) -> Option<&Folder> {
+        match self {
+            DriveNode::Folder(folder) => Some(folder),
+            DriveNode::AppLibrary(library) => Some(&library.folder),
+            _ => None,
+        }
+    }
+
+    pub fn into_folder(self) -> Option<Folder> {
+        match self {
+            DriveNode::Folder(folder) => Some(folder),
+            DriveNode::AppLibrary(library) => Some(library.folder),
+            _ => None,
+        }
+    }
+
+    pub fn id(&self) -> &String {
+        match self {
+            DriveNode::Folder(folder) => &folder.id,
+            DriveNode::AppLibrary(library) => &library.folder.id,
+            DriveNode::File(file) => &file.id,
+            DriveNode::Unknown(node) => &node.id,
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        match self {
+            DriveNode::Folder(folder) => &folder.name,
+            DriveNode::AppLibrary(library) => &library.folder.name,
+            DriveNode::File(file) => &file.name,
+            DriveNode::Unknown(node) => &node.name,
+        }
+    }
+
+    // The name of the node as it appears in a path, with the extension of
+    // a file.
+    pub fn full_name(&self) -> String {
+        match self {
+            DriveNode::File(file) => file.full_name(),
+            _ => self.name().clone(),
+        }
+    }
+
+    pub fn parent_id(&self) -> Option<&String> {
+        match self {
+            DriveNode::Folder(folder) => folder.parent_id.as_ref(),
+            DriveNode::AppLibrary(library) => library.folder.parent_id.as_ref(),
+            DriveNode::File(file) => file.parent_id.as_ref(),
+            DriveNode::Unknown(node) => node.parent_id.as_ref(),
+        }
+    }
+
+    // Why the item could not be read, for one kept as `Unknown` although
+    // its type is known.
+    pub fn read_error(&self) -> Option<&String> {
+        match self {
+            DriveNode::Unknown(node) => node.error.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn etag(&self) -> Option<&String> {
+        match self {
+            DriveNode::Folder(folder) => folder.etag.as_ref(),
+            DriveNode::AppLibrary(library) => library.folder.etag.as_ref(),
+            DriveNode::File(file) => file.etag.as_ref(),
+            DriveNode::Unknown(node) => node.etag.as_ref(),
+        }
+    }
+
+    pub fn date_created(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            DriveNode::Folder(folder) => Some(folder.date_created),
+            DriveNode::AppLibrary(library) => Some(library.folder.date_created),
+            DriveNode::File(file) => Some(file.date_created),
+            DriveNode::Unknown(node) => node.date_created,
+        }
+    }
+}
+
+impl std::fmt::Display for DriveNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            DriveNode::Folder(folder) => {
+                write!(
+                    f,
+                    "Folder(id={},name={},dateCreated={},items={})",
+                    folder.id,
+                    folder.name,
+                    folder.date_created,
+                    folder.items.len()
+                )
+            },
+            DriveNode::File(file) => {
+                write!(
+                    f,
+                    "File(id={},name={},dateCreated={},size={})",
+                    file.id,
+                    file.name,
+                    file.date_created,
+                    file.size,
+                )
+            }
+            DriveNode::AppLibrary(library) => {
+                write!(
+                    f,
+                    "AppLibrary(id={},name={},bundleId={},items={})",
+                    library.folder.id,
+                    library.folder.name,
+                    library.bundle_id,
+                    library.folder.items.len()
+                )
+            }
+            DriveNode::Unknown(node) => {
+                write!(f, "Unknown(id={},type={},name={})", node.id, node.kind, node.name)
+            }
+        }
+    }
+}
+
+// Retrieves the raw details of several nodes in one request, in the order
+// asked for. With `partial_data`, folders are described without their
+// children.
+async fn retrieve_details(
+    session: &Mutex<Session>,
+    url: &str,
+    ids: &[String],
+    partial_data: bool,
+) -> Result<Vec<Value>, Error> {
+    let uri = format!("{}/retrieveItemDetailsInFolders", url);
+    let body: Vec<Value> = ids
+        .iter()
+        .map(|id| json!({ "drivewsid": id, "partialData": partial_data }))
+        .collect();
+    let body = Value::Array(body).to_string();
+
+    let response = Session::request_shared(session, Method::POST, uri, Bytes::from(body), |builder| {
+        if let Some(headers) = builder.headers_mut() {
+            headers.insert("Content-Type", "application/json".parse()?);
+            headers.insert("Accept", "application/json".parse()?);
+        }
+        Ok(())
+    })
+    .await?;
+
+    if response.status() == StatusCode::OK {
+        let body = hyper::body::aggregate(response).await?;
+        match serde_json::from_reader(body.reader())? {
+            Value::Array(details) if details.len() == ids.len() => Ok(details),
+            _ => Err(Error::InvalidResponse(String::from("Unexpected item details"))),
+        }
+    } else {
+        Err(Error::UnexpectedStatus(response.status()))
+    }
+}
+
+pub struct DriveService {
+    session: Arc<Mutex<Session>>,
+    url: String,
+    documents_url: Option<String>,
+    paths: path::PathCache,
+    case_sensitive: bool,
+    cache: Option<cache::SharedCache>,
+    offline: bool,
+}
+
+impl DriveService {
+
+    // Constructs an interface to an iCloud Drive.
+    pub fn new(session: Arc<Mutex<Session>>, url: String) -> DriveService {
+        DriveService {
+            session,
+            url,
+            documents_url: None,
+            paths: path::PathCache::default(),
+            case_sensitive: false,
+            cache: None,
+            offline: false,
+        }
+    }
+
+    // Sets the document service (docws) URL used to transfer file contents.
+    pub fn with_documents_url(mut self, url: String) -> DriveService {
+        self.documents_url = Some(url);
+        self
+    }
+
+    // Sets whether path lookups match names case-sensitively. iCloud Drive
+    // itself is case-insensitive, which is the default.
+    pub fn with_case_sensitive_paths(mut self, case_sensitive: bool) -> DriveService {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    // Reads node metadata through an on-disk cache, which `save_cache`
+    // writes back.
+    pub fn with_cache(mut self, cache: MetadataCache) -> DriveService {
+        self.cache = Some(Arc::new(std::sync::Mutex::new(cache)));
+        self
+    }
+
+    // Serves node metadata from the cache alone, without contacting the
+    // server. Changes are refused while offline.
+    pub fn with_offline(mut self, offline: bool) -> DriveService {
+        self.offline = offline;
+        self
+    }
+
+    pub fn save_cache(&self) -> Result<(), Error> {
+        match &self.cache {
+            Some(cache) => cache.lock().map_err(|_| Error::MutexError)?.save(),
+            None => Ok(()),
+        }
+    }
+
+    // Drops cached metadata of nodes changed by this service.
+    fn invalidate(&mut self, ids: &[String]) -> Result<(), Error> {
+        self.paths.clear();
+        if let Some(cache) = &self.cache {
+            cache.lock().map_err(|_| Error::MutexError)?.invalidate(ids);
+        }
+        Ok(())
+    }
+
+    // Requires all cached metadata to be revalidated before it is used
+    // again, for callers that must see changes made elsewhere.
+    pub(crate) fn expire_cache(&self) -> Result<(), Error> {
+        if let Some(cache) = &self.cache {
+            cache.lock().map_err(|_| Error::MutexError)?.expire_all();
+        }
+        Ok(())
+    }
+
+    fn documents_url(&self) -> Result<&str, Error> {
+        self.documents_url
+            .as_deref()
+            .ok_or_else(|| Error::MissingService(String::from("docws")))
+    }
+
+    // Retrieves the root directory within the iCloud Drive.
+    pub async fn root(&mut self) -> Result<Folder, Error> {
+        match self.get_node("FOLDER::com.apple.CloudDocs::root").await? {
+            DriveNode::Folder(folder) => Ok(folder),
+            _ => Err(Error::InvalidDriveNodeType)
+        }
+    }
+
+    // Lists the children of a folder, fetching them unless the folder
+    // already holds all of them.
+    pub async fn children(&mut self, folder: &Folder) -> Result<Vec<DriveNode>, Error> {
+        if folder.is_complete() {
+            Ok(folder.items.clone())
+        } else {
+            Ok(self.refresh(folder).await?.items)
+        }
+    }
+
+    // Fetches a folder again, with all of its children. A cached listing is
+    // used only if the server confirms it is current.
+    pub async fn refresh(&mut self, folder: &Folder) -> Result<Folder, Error> {
+        if let Some(cache) = &self.cache {
+            cache.lock().map_err(|_| Error::MutexError)?.expire(&folder.id);
+        }
+        self.get_node(&folder.id)
+            .await?
+            .into_folder()
+            .ok_or(Error::InvalidDriveNodeType)
+    }
+
+    // Lists the app libraries, the containers of Pages, Numbers, Keynote and
+    // other apps that store documents in iCloud Drive.
+    pub async fn app_libraries(&mut self) -> Result<Vec<AppLibrary>, Error> {
+        let uri = format!("{}/retrieveAppLibraries", self.url);
+
+        let mut session = self.session.lock().await;
+        let response = session
+            .request(Method::GET, uri, Bytes::new(), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let body = hyper::body::aggregate(response).await?;
+        let libraries: Value = serde_json::from_reader(body.reader())?;
+        let items = libraries["items"]
+            .as_array()
+            .ok_or_else(|| Error::InvalidResponse(String::from("Missing items")))?;
+        let mut libraries = Vec::new();
+        for (index, item) in items.iter().enumerate() {
+            if let DriveNode::AppLibrary(library) = DriveNode::parse(item, &format!("items[{}]", index))? {
+                libraries.push(library);
+            }
+        }
+        Ok(libraries)
+    }
+
+    // Retrieves a node within the iCloud Drive.
+    pub async fn get_node(&mut self, id: &str) -> Result<DriveNode, Error> {
+        DriveNode::new(&self.details(id).await?)
+    }
+
+    // Retrieves several nodes, in as few requests as possible. The result
+    // for each id is reported separately, in the order given.
+    pub async fn get_nodes(&mut self, ids: &[&str]) -> Result<Vec<Result<DriveNode, Error>>, Error> {
+        self.retrieve_nodes(ids, false).await
+    }
+
+    // Retrieves several nodes like `get_nodes`, but without the children of
+    // folders, which makes the reply much smaller when they aren't needed.
+    pub async fn get_nodes_partial(&mut self, ids: &[&str]) -> Result<Vec<Result<DriveNode, Error>>, Error> {
+        self.retrieve_nodes(ids, true).await
+    }
+
+    async fn retrieve_nodes(
+        &self,
+        ids: &[&str],
+        partial_data: bool,
+    ) -> Result<Vec<Result<DriveNode, Error>>, Error> {
+        let mut results = Vec::with_capacity(ids.len());
+        for batch in ids.chunks(BATCH_SIZE) {
+            let batch: Vec<String> = batch.iter().map(|id| String::from(*id)).collect();
+            let details = if partial_data && !self.offline {
+                retrieve_details(&self.session, &self.url, &batch, true).await?
+            } else {
+                cache::fetch_details(&self.session, &self.url, self.cache.as_ref(), self.offline, &batch).await?
+            };
+            results.extend(details.iter().map(parse_item));
+        }
+        Ok(results)
+    }
+
+    // Retrieves the raw details of a node, with its children for folders.
+    async fn details(&self, id: &str) -> Result<Value, Error> {
+        let ids = [String::from(id)];
+        let mut details =
+            cache::fetch_details(&self.session, &self.url, self.cache.as_ref(), self.offline, &ids).await?;
+        let details = details
+            .pop()
+            .ok_or_else(|| Error::InvalidResponse(String::from("Missing item")))?;
+        parse_status(&details)?;
+        Ok(details)
+    }
+
+    // Posts a change to a drivews endpoint and returns the JSON reply. A
+    // refused change is reported as `Error::Conflict` with the ids of the
+    // items sent, or of the destination when no items were. Cached metadata
+    // of the nodes involved is dropped, along with the trash, which most
+    // changes touch, and the folders the reply places items in, which for
+    // restored items aren't known beforehand.
+    async fn post(&mut self, endpoint: &str, body: Value) -> Result<Value, Error> {
+        if self.offline {
+            return Err(Error::Offline(String::from(endpoint)));
+        }
+        let items: Vec<String> = body["items"]
+            .as_array()
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|item| item["drivewsid"].as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let destination = body["destinationDrivewsId"].as_str().map(String::from);
+        let mut changed = items.clone();
+        changed.extend(destination.clone());
+        changed.push(String::from("TRASH_ROOT"));
+        self.invalidate(&changed)?;
+
+        let uri = format!("{}/{}", self.url, endpoint);
+
+        let mut session = self.session.lock().await;
+        let response = session
+            .request(Method::POST, uri, Bytes::from(body.to_string()), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        drop(session);
+
+        match response.status() {
+            StatusCode::OK => {
+                let body = hyper::body::aggregate(response).await?;
+                let reply: Value = serde_json::from_reader(body.reader())?;
+                let parents: Vec<String> = ["items", "folders"]
+                    .iter()
+                    .filter_map(|key| reply[key].as_array())
+                    .flatten()
+                    .filter_map(|item| item["parentId"].as_str().map(String::from))
+                    .collect();
+                self.invalidate(&parents)?;
+                Ok(reply)
+            }
+            StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => {
+                let conflicting = if items.is_empty() {
+                    destination.unwrap_or_default()
+                } else {
+                    items.join(", ")
+                };
+                Err(Error::Conflict(conflicting))
+            }
+            status => Err(Error::UnexpectedStatus(status)),
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use super::{DriveNode, DriveService, Folder};
+use crate::error::Error;
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+
+static ROOT_ID: &str = "FOLDER::com.apple.CloudDocs::root";
+
+// The most resolved paths kept before the cache is emptied.
+const CACHE_CAPACITY: usize = 256;
+
+struct CachedPath {
+    id: String,
+    path: String,
+    // The parent and name the folder had when it was resolved, to tell
+    // whether it has since been moved or renamed.
+    parent_id: Option<String>,
+    name: String,
+}
+
+// Resolved path prefixes, keyed by their normalized form, so lookups
+// beneath the same folder don't walk the tree from the root each time.
+#[derive(Default)]
+pub(crate) struct PathCache {
+    paths: BTreeMap<String, CachedPath>,
+}
+
+impl PathCache {
+    fn get(&self, key: &str) -> Option<&CachedPath> {
+        self.paths.get(key)
+    }
+
+    fn insert(&mut self, key: String, folder: &DriveNode, path: String) {
+        if self.paths.len() >= CACHE_CAPACITY {
+            self.paths.clear();
+        }
+        self.paths.insert(
+            key,
+            CachedPath {
+                id: folder.id().clone(),
+                path,
+                parent_id: folder.parent_id().cloned(),
+                name: folder.full_name(),
+            },
+        );
+    }
+
+    // Drops a prefix along with every path beneath it.
+    fn remove(&mut self, key: &str) {
+        let beneath = format!("{}/", key);
+        self.paths
+            .retain(|cached, _| cached != key && !cached.starts_with(&beneath));
+    }
+
+    // The key and path a node was resolved at, if it is cached.
+    fn path_of(&self, id: &str) -> Option<(String, String)> {
+        self.paths
+            .iter()
+            .find(|(_, cached)| cached.id == id)
+            .map(|(key, cached)| (key.clone(), cached.path.clone()))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.paths.clear();
+    }
+}
+
+// Brings a name to the form used to compare names: NFC, so names typed on
+// different platforms match, and lower case unless matching is
+// case-sensitive.
+fn normalize(name: &str, case_sensitive: bool) -> String {
+    let name: String = name.nfc().collect();
+    if case_sensitive {
+        name
+    } else {
+        name.to_lowercase()
+    }
+}
+
+impl DriveService {
+    fn path_key(&self, components: &[&str]) -> String {
+        components
+            .iter()
+            .map(|component| normalize(component, self.case_sensitive))
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+
+    // Retrieves the node at a path such as `/Documents/Reports/q3.pdf`,
+    // relative to the drive root.
+    pub async fn get_by_path(&mut self, path: &str) -> Result<DriveNode, Error> {
+        let components: Vec<&str> = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect();
+
+        let cached = match self.cached_prefix(&components) {
+            Some((len, _)) => self.check_cached(&self.path_key(&components[..len])).await?,
+            None => false,
+        };
+        match self.resolve(path, &components, cached).await {
+            // A cached folder may have been changed by another client since
+            // it was resolved; look again from the root before giving up.
+            Err(_) if cached => {
+                self.paths.clear();
+                self.resolve(path, &components, false).await
+            }
+            result => result,
+        }
+    }
+
+    // Whether the folders along a cached prefix still have the parent and
+    // name they were resolved with. If one was moved or renamed since, the
+    // prefix and everything beneath it is dropped.
+    async fn check_cached(&mut self, key: &str) -> Result<bool, Error> {
+        let components: Vec<&str> = key.split('/').collect();
+        let mut ids = Vec::with_capacity(components.len());
+        let mut expected = Vec::with_capacity(components.len());
+        for len in 1..=components.len() {
+            match self.paths.get(&self.path_key(&components[..len])) {
+                Some(cached) => {
+                    ids.push(cached.id.clone());
+                    expected.push((cached.parent_id.clone(), cached.name.clone()));
+                }
+                None => {
+                    self.paths.remove(key);
+                    return Ok(false);
+                }
+            }
+        }
+
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let nodes = self.get_nodes_partial(&ids).await?;
+        let unchanged = nodes.iter().zip(&expected).all(|(node, (parent_id, name))| match node {
+            Ok(node) => node.parent_id() == parent_id.as_ref() && node.full_name() == *name,
+            Err(_) => false,
+        });
+        if !unchanged {
+            self.paths.remove(key);
+        }
+        Ok(unchanged)
+    }
+
+    // The longest cached prefix of `components`, excluding the last one.
+    fn cached_prefix(&self, components: &[&str]) -> Option<(usize, &CachedPath)> {
+        (1..components.len())
+            .rev()
+            .find_map(|len| {
+                self.paths
+                    .get(&self.path_key(&components[..len]))
+                    .map(|cached| (len, cached))
+            })
+    }
+
+    async fn resolve(&mut self, path: &str, components: &[&str], use_cache: bool) -> Result<DriveNode, Error> {
+        let (start, mut folder_id, mut resolved) = match self.cached_prefix(components) {
+            Some((len, cached)) if use_cache => (len, cached.id.clone(), cached.path.clone()),
+            _ => (0, String::from(ROOT_ID), String::new()),
+        };
+
+        for index in start..components.len() {
+            let folder = self
+                .get_node(&folder_id)
+                .await?
+                .into_folder()
+                .ok_or_else(|| Error::NotFound(String::from(path)))?;
+            let child = self
+                .find_child(&folder, components[index])
+                .ok_or_else(|| Error::NotFound(String::from(path)))?;
+
+            resolved = format!("{}/{}", resolved, child.full_name());
+            if child.as_folder().is_some() {
+                self.paths
+                    .insert(self.path_key(&components[..=index]), &child, resolved.clone());
+            }
+            if index + 1 == components.len() {
+                // Children listed in a folder don't carry their own items.
+                return match child.as_folder() {
+                    Some(folder) => self.get_node(&folder.id).await,
+                    None => Ok(child),
+                };
+            }
+            folder_id = child.id().clone();
+        }
+
+        self.get_node(&folder_id).await
+    }
+
+    // Finds the child of a folder with a name, preferring an exact match
+    // over one that only matches once normalized.
+    pub(super) fn find_child(&self, folder: &Folder, name: &str) -> Option<DriveNode> {
+        folder
+            .items
+            .iter()
+            .find(|child| child.full_name() == name)
+            .or_else(|| {
+                let name = normalize(name, self.case_sensitive);
+                folder
+                    .items
+                    .iter()
+                    .find(|child| normalize(&child.full_name(), self.case_sensitive) == name)
+            })
+            .cloned()
+    }
+
+    // Retrieves the folder containing a node, or `None` for the root. The
+    // folder of an app library's top-level items is the library itself.
+    pub async fn parent(&mut self, node: &DriveNode) -> Result<Option<Folder>, Error> {
+        match node.parent_id() {
+            Some(parent_id) => match self.get_node(parent_id).await?.into_folder() {
+                Some(folder) => Ok(Some(folder)),
+                None => Err(Error::InvalidDriveNodeType),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Builds the path of a node relative to the drive root, e.g.
+    // `/Documents/Reports/q3.pdf`.
+    pub async fn path_of(&mut self, node: &DriveNode) -> Result<String, Error> {
+        if node.id() == ROOT_ID {
+            return Ok(String::from("/"));
+        }
+
+        let mut names = vec![node.full_name()];
+        let mut parent_id = node.parent_id().cloned();
+        let mut prefix = String::new();
+        while let Some(id) = parent_id {
+            if id == ROOT_ID {
+                break;
+            }
+            if let Some((key, path)) = self.paths.path_of(&id) {
+                if self.check_cached(&key).await? {
+                    prefix = path;
+                    break;
+                }
+            }
+            let parent = self.get_node(&id).await?;
+            names.push(parent.full_name());
+            parent_id = parent.parent_id().cloned();
+        }
+
+        names.reverse();
+        Ok(format!("{}/{}", prefix, names.join("/")))
+    }
+}
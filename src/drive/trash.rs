@@ -0,0 +1,75 @@
+use super::{
+    batch_items, item_results, parse_status, reply_items, DriveNode, DriveService, UnknownNode, BATCH_SIZE,
+};
+use crate::error::Error;
+use chrono::{DateTime, FixedOffset};
+use serde_json::json;
+
+static TRASH_ID: &str = "TRASH_ROOT";
+
+// An item in "Recently Deleted".
+#[derive(Clone)]
+pub struct TrashItem {
+    pub node: DriveNode,
+    // The path the item was deleted from, relative to the drive root.
+    pub original_path: Option<String>,
+    pub date_deleted: Option<DateTime<FixedOffset>>,
+}
+
+impl DriveService {
+    // Lists the items in "Recently Deleted". An item that can't be read is
+    // listed as `DriveNode::Unknown` with the reason, rather than failing
+    // the whole listing.
+    pub async fn trash(&mut self) -> Result<Vec<TrashItem>, Error> {
+        let details = self.details(TRASH_ID).await?;
+        let items = details["items"].as_array().cloned().unwrap_or_default();
+        Ok(items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| TrashItem {
+                node: DriveNode::parse(item, &format!("items[{}]", index))
+                    .unwrap_or_else(|err| DriveNode::Unknown(UnknownNode::malformed(item, err))),
+                original_path: item["restorePath"].as_str().map(String::from),
+                date_deleted: item["dateDeleted"]
+                    .as_str()
+                    .and_then(|date| DateTime::parse_from_rfc3339(date).ok()),
+            })
+            .collect())
+    }
+
+    // Puts items in the trash back where they were deleted from. The result
+    // for each item is reported separately, in the order given.
+    pub async fn restore(&mut self, nodes: &[&DriveNode]) -> Result<Vec<Result<DriveNode, Error>>, Error> {
+        let mut results = Vec::with_capacity(nodes.len());
+        for batch in nodes.chunks(BATCH_SIZE) {
+            let reply = self
+                .post("putBackItemsFromTrash", json!({ "items": batch_items(batch) }))
+                .await?;
+            results.extend(item_results(&reply, batch.len())?);
+        }
+        Ok(results)
+    }
+
+    // Permanently deletes items. The result for each item is reported
+    // separately, in the order given.
+    pub async fn purge(&mut self, nodes: &[&DriveNode]) -> Result<Vec<Result<(), Error>>, Error> {
+        let mut results = Vec::with_capacity(nodes.len());
+        for batch in nodes.chunks(BATCH_SIZE) {
+            let reply = self
+                .post("deleteItems", json!({ "items": batch_items(batch) }))
+                .await?;
+            results.extend(reply_items(&reply, batch.len())?.iter().map(parse_status));
+        }
+        Ok(results)
+    }
+
+    // Permanently deletes everything in the trash. Each item is returned
+    // with the result of deleting it, so that one which can't be deleted
+    // doesn't stop the rest.
+    pub async fn empty_trash(&mut self) -> Result<Vec<(TrashItem, Result<(), Error>)>, Error> {
+        let items = self.trash().await?;
+        let nodes: Vec<&DriveNode> = items.iter().map(|item| &item.node).collect();
+        let results = self.purge(&nodes).await?;
+        Ok(items.into_iter().zip(results).collect())
+    }
+}
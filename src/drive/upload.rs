@@ -0,0 +1,264 @@
+use super::{DriveNode, DriveService, File, Folder};
+use crate::error::Error;
+use crate::session::Session;
+use futures::io::{AsyncRead, AsyncReadExt};
+use futures::{stream, StreamExt};
+use hyper::body::{Buf, Bytes};
+use hyper::{Body, Method, StatusCode};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// The receipt returned after the contents of a file have been uploaded.
+struct UploadReceipt {
+    signature: String,
+    wrapping_key: String,
+    reference_signature: String,
+    receipt: String,
+    size: u64,
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase()) {
+        Some(extension) => match extension.as_str() {
+            "txt" => "text/plain",
+            "json" => "application/json",
+            "pdf" => "application/pdf",
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "zip" => "application/zip",
+            _ => "application/octet-stream",
+        },
+        None => "application/octet-stream",
+    }
+}
+
+impl DriveService {
+    // Uploads `size` bytes from `source` as a file named `name` in
+    // `parent`. A file of the same name, compared the way path lookups
+    // compare names, is updated in place.
+    pub async fn upload<R>(
+        &mut self,
+        parent: &Folder,
+        name: &str,
+        source: R,
+        size: u64,
+    ) -> Result<File, Error>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let existing = match self.get_node(&parent.id).await?.into_folder() {
+            Some(folder) => match self.find_child(&folder, name) {
+                Some(DriveNode::File(file)) => Some(file.docwsid),
+                _ => None,
+            },
+            None => return Err(Error::InvalidDriveNodeType),
+        };
+
+        let (document_id, upload_url) = self
+            .request_upload(parent, name, size, existing.as_deref())
+            .await?;
+        let receipt = self.send_contents(&upload_url, name, source, size).await?;
+        self.commit_upload(parent, name, &document_id, existing.is_some(), receipt)
+            .await?;
+        let file_id = format!("FILE::{}::{}", parent.zone, document_id);
+        self.invalidate(&[parent.id.clone(), file_id.clone()])?;
+
+        match self.get_node(&file_id).await? {
+            DriveNode::File(file) => Ok(file),
+            _ => Err(Error::InvalidDriveNodeType),
+        }
+    }
+
+    // Asks docws for a URL to send the contents to.
+    async fn request_upload(
+        &mut self,
+        parent: &Folder,
+        name: &str,
+        size: u64,
+        document_id: Option<&str>,
+    ) -> Result<(String, String), Error> {
+        let uri = format!("{}/ws/{}/upload/web", self.documents_url()?, parent.zone);
+        let mut body = json!({
+            "filename": name,
+            "type": "FILE",
+            "content_type": content_type(name),
+            "size": size,
+        });
+        if let Some(document_id) = document_id {
+            body["document_id"] = json!(document_id);
+        }
+
+        let mut session = self.session.lock().await;
+        let response = session
+            .request(Method::POST, uri, Bytes::from(body.to_string()), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let body = hyper::body::aggregate(response).await?;
+        let upload: serde_json::Value = serde_json::from_reader(body.reader())?;
+        match (upload[0]["document_id"].as_str(), upload[0]["url"].as_str()) {
+            (Some(document_id), Some(url)) => Ok((String::from(document_id), String::from(url))),
+            _ => Err(Error::InvalidResponse(String::from("Missing upload URL"))),
+        }
+    }
+
+    // Streams the contents as a multipart form and collects the receipt.
+    async fn send_contents<R>(
+        &mut self,
+        url: &str,
+        name: &str,
+        source: R,
+        size: u64,
+    ) -> Result<UploadReceipt, Error>
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let boundary = format!("----icloud-rs-{}", SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos());
+        let preamble = Bytes::from(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"files\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            boundary,
+            name.replace('"', "%22"),
+            content_type(name)
+        ));
+        let epilogue = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+        let length = preamble.len() as u64 + size + epilogue.len() as u64;
+
+        let contents = stream::unfold(source.take(size), |mut source| async move {
+            let mut buffer = vec![0; CHUNK_SIZE];
+            match source.read(&mut buffer).await {
+                Ok(0) => None,
+                Ok(read) => {
+                    buffer.truncate(read);
+                    Some((Ok(Bytes::from(buffer)), source))
+                }
+                Err(err) => Some((Err(err), source)),
+            }
+        });
+        let body = stream::once(async move { Ok(preamble) })
+            .chain(contents)
+            .chain(stream::once(async move { Ok(epilogue) }));
+
+        let response = Session::request_streaming(
+            &self.session,
+            Method::POST,
+            String::from(url),
+            Body::wrap_stream(body),
+            |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert(
+                        "Content-Type",
+                        format!("multipart/form-data; boundary={}", boundary).parse()?,
+                    );
+                    headers.insert(hyper::header::CONTENT_LENGTH, length.into());
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            },
+        )
+        .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let body = hyper::body::aggregate(response).await?;
+        let result: serde_json::Value = serde_json::from_reader(body.reader())?;
+        let file = &result["singleFile"];
+        let field = |key: &str| {
+            file[key]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| Error::InvalidResponse(format!("Missing singleFile.{}", key)))
+        };
+        Ok(UploadReceipt {
+            signature: field("fileChecksum")?,
+            wrapping_key: field("wrappingKey")?,
+            reference_signature: field("referenceChecksum")?,
+            receipt: field("receipt")?,
+            size: file["size"].as_u64().unwrap_or(size),
+        })
+    }
+
+    // Commits the uploaded contents into the target folder. A document
+    // changed by someone else since the upload began is reported as
+    // `Error::Conflict`.
+    async fn commit_upload(
+        &mut self,
+        parent: &Folder,
+        name: &str,
+        document_id: &str,
+        replace: bool,
+        receipt: UploadReceipt,
+    ) -> Result<(), Error> {
+        let uri = format!("{}/ws/{}/update/documents", self.documents_url()?, parent.zone);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let body = json!({
+            "data": {
+                "signature": receipt.signature,
+                "wrapping_key": receipt.wrapping_key,
+                "reference_signature": receipt.reference_signature,
+                "receipt": receipt.receipt,
+                "size": receipt.size,
+            },
+            "command": if replace { "modify_file" } else { "add_file" },
+            "create_short_guid": true,
+            "document_id": document_id,
+            "path": {
+                "starting_document_id": parent.docwsid,
+                "path": name,
+            },
+            "allow_conflict": !replace,
+            "file_flags": {
+                "is_writable": true,
+                "is_executable": false,
+                "is_hidden": false,
+            },
+            "mtime": now,
+            "btime": now,
+        })
+        .to_string();
+
+        let mut session = self.session.lock().await;
+        let response = session
+            .request(Method::POST, uri, Bytes::from(body), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(Error::UnexpectedStatus(response.status()));
+        }
+
+        let body = hyper::body::aggregate(response).await?;
+        let reply: serde_json::Value = serde_json::from_reader(body.reader())?;
+        let results = reply["results"]
+            .as_array()
+            .ok_or_else(|| Error::InvalidResponse(String::from("Missing upload results")))?;
+        for result in results {
+            let status = &result["status"];
+            match (status["status_code"].as_i64(), status["error_message"].as_str()) {
+                (Some(0), _) => {}
+                (_, Some("ETAG_CONFLICT")) => {
+                    return Err(Error::Conflict(format!("FILE::{}::{}", parent.zone, document_id)));
+                }
+                _ => return Err(Error::InvalidResponse(format!("{}: {}", document_id, status))),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+use super::cache::{fetch_details, SharedCache};
+use super::{DriveNode, DriveService, Folder, BATCH_SIZE};
+use crate::error::Error;
+use crate::session::Session;
+use futures::future::BoxFuture;
+use futures::lock::Mutex;
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A predicate on the path and node of an item found by a walk.
+pub type WalkFilter = Arc<dyn Fn(&Path, &DriveNode) -> bool + Send + Sync>;
+
+// The order in which a walk reports items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkOrder {
+    // Every item at one depth before any item deeper down.
+    BreadthFirst,
+    // Each folder followed by everything beneath it.
+    DepthFirst,
+}
+
+// How `DriveService::walk` traverses the tree.
+#[derive(Clone)]
+pub struct WalkOptions {
+    max_depth: Option<usize>,
+    concurrency: usize,
+    order: WalkOrder,
+    include: Option<WalkFilter>,
+    exclude: Option<WalkFilter>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> WalkOptions {
+        WalkOptions {
+            max_depth: None,
+            concurrency: 4,
+            order: WalkOrder::BreadthFirst,
+            include: None,
+            exclude: None,
+        }
+    }
+}
+
+impl WalkOptions {
+    pub fn new() -> WalkOptions {
+        WalkOptions::default()
+    }
+
+    // Stops descending below this depth; the children of the root are at
+    // depth 1.
+    pub fn max_depth(mut self, max_depth: usize) -> WalkOptions {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    // The most folder fetches in flight at once. Each fetch asks for up to
+    // 100 folders.
+    pub fn concurrency(mut self, concurrency: usize) -> WalkOptions {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn order(mut self, order: WalkOrder) -> WalkOptions {
+        self.order = order;
+        self
+    }
+
+    // Reports only the items this accepts. Folders that are not reported
+    // are still descended into.
+    pub fn include<F>(mut self, filter: F) -> WalkOptions
+    where
+        F: Fn(&Path, &DriveNode) -> bool + Send + Sync + 'static,
+    {
+        self.include = Some(Arc::new(filter));
+        self
+    }
+
+    // Skips the items this accepts, along with everything beneath them.
+    pub fn exclude<F>(mut self, filter: F) -> WalkOptions
+    where
+        F: Fn(&Path, &DriveNode) -> bool + Send + Sync + 'static,
+    {
+        self.exclude = Some(Arc::new(filter));
+        self
+    }
+}
+
+enum Entry {
+    // An item to report.
+    Node(PathBuf, Box<DriveNode>),
+    // A folder whose children are still to be listed.
+    Expand(PathBuf, String, usize),
+}
+
+type Batch = (Vec<String>, Result<Vec<Value>, Error>);
+
+struct Walk {
+    session: Arc<Mutex<Session>>,
+    url: String,
+    cache: Option<SharedCache>,
+    offline: bool,
+    options: WalkOptions,
+    // What is still to be reported or listed, in the order of the walk.
+    entries: VecDeque<Entry>,
+    // The folders in `entries` not yet requested, in the same order.
+    pending: VecDeque<String>,
+    requested: BTreeSet<String>,
+    listings: BTreeMap<String, Result<Vec<DriveNode>, Error>>,
+    in_flight: FuturesUnordered<BoxFuture<'static, Batch>>,
+}
+
+// Lists the children of a folder from its details.
+fn listing(details: &Value) -> Result<Vec<DriveNode>, Error> {
+    DriveNode::new(details)?
+        .into_folder()
+        .map(|folder| folder.items)
+        .ok_or(Error::InvalidDriveNodeType)
+}
+
+impl Walk {
+    // Starts fetching the next folders the walk will list, a batch at a
+    // time, until the concurrency cap is reached.
+    fn prefetch(&mut self) {
+        while self.in_flight.len() < self.options.concurrency && !self.pending.is_empty() {
+            let count = self.pending.len().min(BATCH_SIZE);
+            let batch: Vec<String> = self.pending.drain(..count).collect();
+            self.requested.extend(batch.iter().cloned());
+            let session = self.session.clone();
+            let url = self.url.clone();
+            let cache = self.cache.clone();
+            let offline = self.offline;
+            self.in_flight.push(Box::pin(async move {
+                let details = fetch_details(&session, &url, cache.as_ref(), offline, &batch).await;
+                (batch, details)
+            }));
+        }
+    }
+
+    // Waits until the children of a folder are listed.
+    async fn wait_for(&mut self, id: &str) -> Result<Vec<DriveNode>, Error> {
+        loop {
+            if let Some(listing) = self.listings.remove(id) {
+                return listing;
+            }
+            if !self.requested.contains(id) {
+                self.prefetch();
+            }
+            match self.in_flight.next().await {
+                Some((ids, Ok(details))) => {
+                    for (id, details) in ids.into_iter().zip(details.iter()) {
+                        self.listings.insert(id, listing(details));
+                    }
+                }
+                Some((ids, Err(err))) => {
+                    // Each folder in the batch is reported once, with the
+                    // original error going to the first.
+                    let message = err.to_string();
+                    let mut err = Some(err);
+                    for id in ids {
+                        let err = err.take().unwrap_or_else(|| {
+                            Error::InvalidResponse(format!("Could not list {}: {}", id, message))
+                        });
+                        self.listings.insert(id, Err(err));
+                    }
+                }
+                None => return Err(Error::InvalidResponse(format!("Missing listing of {}", id))),
+            }
+        }
+    }
+
+    fn excluded(&self, path: &Path, node: &DriveNode) -> bool {
+        self.options
+            .exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude(path, node))
+    }
+
+    fn included(&self, path: &Path, node: &DriveNode) -> bool {
+        self.options
+            .include
+            .as_ref()
+            .is_none_or(|include| include(path, node))
+    }
+
+    // Queues the children of a folder at `depth`.
+    fn expand(&mut self, path: &Path, depth: usize, children: Vec<DriveNode>) {
+        let descend = self.options.max_depth.is_none_or(|max_depth| depth + 1 < max_depth);
+        let mut nodes = Vec::new();
+        let mut folders = Vec::new();
+        let mut ids = Vec::new();
+        for child in children {
+            let child_path = path.join(child.full_name());
+            if self.excluded(&child_path, &child) {
+                continue;
+            }
+            let folder = match child.as_folder() {
+                Some(folder) if descend => Some(folder.id.clone()),
+                _ => None,
+            };
+            ids.extend(folder.clone());
+            match self.options.order {
+                WalkOrder::BreadthFirst => {
+                    if let Some(id) = folder {
+                        folders.push(Entry::Expand(child_path.clone(), id, depth + 1));
+                    }
+                    nodes.push(Entry::Node(child_path, Box::new(child)));
+                }
+                WalkOrder::DepthFirst => {
+                    nodes.push(Entry::Node(child_path.clone(), Box::new(child)));
+                    if let Some(id) = folder {
+                        nodes.push(Entry::Expand(child_path, id, depth + 1));
+                    }
+                }
+            }
+        }
+        for entry in nodes.into_iter().rev() {
+            self.entries.push_front(entry);
+        }
+        self.entries.extend(folders);
+        match self.options.order {
+            WalkOrder::BreadthFirst => self.pending.extend(ids),
+            WalkOrder::DepthFirst => {
+                for id in ids.into_iter().rev() {
+                    self.pending.push_front(id);
+                }
+            }
+        }
+    }
+
+    async fn next(&mut self) -> Option<Result<(PathBuf, DriveNode), Error>> {
+        loop {
+            self.prefetch();
+            match self.entries.pop_front()? {
+                Entry::Node(path, node) => {
+                    if self.included(&path, &node) {
+                        return Some(Ok((path, *node)));
+                    }
+                }
+                Entry::Expand(path, id, depth) => match self.wait_for(&id).await {
+                    Ok(children) => self.expand(&path, depth, children),
+                    Err(err) => return Some(Err(err)),
+                },
+            }
+        }
+    }
+}
+
+impl DriveService {
+    // Walks everything beneath `root`, reporting each item with its path
+    // relative to `root`. Folders are listed in batches, ahead of the walk,
+    // so a large tree takes a few requests per level rather than one per
+    // folder. A folder that cannot be listed is reported as an error and
+    // the walk carries on.
+    pub fn walk(
+        &self,
+        root: &Folder,
+        options: WalkOptions,
+    ) -> impl Stream<Item = Result<(PathBuf, DriveNode), Error>> + Send + 'static {
+        let mut entries = VecDeque::new();
+        let mut pending = VecDeque::new();
+        if options.max_depth != Some(0) {
+            entries.push_back(Entry::Expand(PathBuf::new(), root.id.clone(), 0));
+            pending.push_back(root.id.clone());
+        }
+        let walk = Walk {
+            session: self.session.clone(),
+            url: self.url.clone(),
+            cache: self.cache.clone(),
+            offline: self.offline,
+            options,
+            entries,
+            pending,
+            requested: BTreeSet::new(),
+            listings: BTreeMap::new(),
+            in_flight: FuturesUnordered::new(),
+        };
+        futures::stream::unfold(walk, |mut walk| async move {
+            walk.next().await.map(|item| (item, walk))
+        })
+    }
+}
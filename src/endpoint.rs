@@ -0,0 +1,45 @@
+// The base URLs used to sign in and set up an iCloud session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoints {
+    // The Apple ID authentication service, e.g. `https://idmsa.apple.com/appleauth/auth`.
+    pub auth: String,
+    // The iCloud setup service, e.g. `https://setup.icloud.com/setup/ws/1`.
+    pub setup: String,
+    // The iCloud web origin sent as `Origin` and `Referer`.
+    pub home: String,
+}
+
+impl Endpoints {
+    // The endpoints used by accounts outside mainland China.
+    pub fn global() -> Endpoints {
+        Endpoints {
+            auth: String::from("https://idmsa.apple.com/appleauth/auth"),
+            setup: String::from("https://setup.icloud.com/setup/ws/1"),
+            home: String::from("https://www.icloud.com"),
+        }
+    }
+
+    // The endpoints used by mainland China accounts.
+    pub fn china() -> Endpoints {
+        Endpoints {
+            auth: String::from("https://idmsa.apple.com/appleauth/auth"),
+            setup: String::from("https://setup.icloud.com.cn/setup/ws/1"),
+            home: String::from("https://www.icloud.com.cn"),
+        }
+    }
+
+    // Endpoints at arbitrary URLs, such as a local test server.
+    pub fn custom(auth: &str, setup: &str, home: &str) -> Endpoints {
+        Endpoints {
+            auth: String::from(auth.trim_end_matches('/')),
+            setup: String::from(setup.trim_end_matches('/')),
+            home: String::from(home.trim_end_matches('/')),
+        }
+    }
+}
+
+impl Default for Endpoints {
+    fn default() -> Endpoints {
+        Endpoints::global()
+    }
+}
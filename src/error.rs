@@ -10,12 +10,26 @@ pub enum Error {
     JsonError(serde_json::Error),
     InvalidStatusCode(http::status::InvalidStatusCode),
     InvalidHeaderValue(http::header::InvalidHeaderValue),
+    InvalidUri(http::uri::InvalidUri),
+    InvalidHeaderName(http::header::InvalidHeaderName),
     ParseError(chrono::format::ParseError),
+    Base64Error(base64::DecodeError),
     InvalidDriveNodeType,
     InvalidCredentials,
     Needs2FA,
     AuthenticationFailed(String),
     TrustFailed,
+    UnsupportedProtocol(String),
+    UnexpectedRequest(String),
+    MissingService(String),
+    ServiceUnavailable(String),
+    UnexpectedStatus(hyper::StatusCode),
+    InvalidResponse(String),
+    Conflict(String),
+    NotFound(String),
+    Offline(String),
+    InvalidField(String, String),
+    InvalidSyncState(String),
     MutexError,
 }
 
@@ -52,9 +66,18 @@ impl std::fmt::Display for Error {
             Error::InvalidHeaderValue(err) => {
                 write!(f, "{}", err)
             }
+            Error::InvalidUri(err) => {
+                write!(f, "{}", err)
+            }
+            Error::InvalidHeaderName(err) => {
+                write!(f, "{}", err)
+            }
             Error::ParseError(err) => {
                 write!(f, "{}", err)
             }
+            Error::Base64Error(err) => {
+                write!(f, "{}", err)
+            }
             Error::MutexError => {
                 write!(f, "Mutex error")
             }
@@ -73,6 +96,42 @@ impl std::fmt::Display for Error {
             Error::TrustFailed => {
                 write!(f, "Trust failed.")
             }
+            Error::UnsupportedProtocol(protocol) => {
+                write!(f, "Unsupported protocol: {}", protocol)
+            }
+            Error::UnexpectedRequest(request) => {
+                write!(f, "Unexpected request: {}", request)
+            }
+            Error::MissingService(service) => {
+                write!(f, "Service not provided for this account: {}", service)
+            }
+            Error::ServiceUnavailable(service) => {
+                write!(f, "Service unavailable: {}", service)
+            }
+            Error::UnexpectedStatus(status) => {
+                write!(f, "Unexpected response status: {}", status)
+            }
+            Error::InvalidResponse(message) => {
+                write!(f, "Invalid response: {}", message)
+            }
+            Error::Conflict(id) => {
+                write!(f, "Conflicting change to {}", id)
+            }
+            Error::NotFound(path) => {
+                write!(f, "No such item: {}", path)
+            }
+            Error::Offline(item) => {
+                write!(f, "Not available offline: {}", item)
+            }
+            Error::InvalidField(field, message) if field.is_empty() => {
+                write!(f, "Invalid drive item: {}", message)
+            }
+            Error::InvalidField(field, message) => {
+                write!(f, "Invalid drive item field {}: {}", field, message)
+            }
+            Error::InvalidSyncState(message) => {
+                write!(f, "Invalid sync state: {}", message)
+            }
         }
     }
 }
@@ -131,8 +190,26 @@ impl From<http::header::InvalidHeaderValue> for Error {
     }
 }
 
+impl From<http::uri::InvalidUri> for Error {
+    fn from(error: http::uri::InvalidUri) -> Error {
+        Error::InvalidUri(error)
+    }
+}
+
+impl From<http::header::InvalidHeaderName> for Error {
+    fn from(error: http::header::InvalidHeaderName) -> Error {
+        Error::InvalidHeaderName(error)
+    }
+}
+
 impl From<chrono::format::ParseError> for Error {
     fn from(error: chrono::format::ParseError) -> Error {
         Error::ParseError(error)
     }
 }
+
+impl From<base64::DecodeError> for Error {
+    fn from(error: base64::DecodeError) -> Error {
+        Error::Base64Error(error)
+    }
+}
@@ -1,7 +1,19 @@
+pub mod account;
+pub mod backup;
 pub mod client;
 pub mod drive;
+pub mod endpoint;
 pub mod error;
 mod session;
+mod store;
+pub mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transport;
 
-pub use session::SessionData;
-pub use client::Client;
+pub use session::{
+    Cookie, CookieJar, Service, ServiceInfo, SessionData, SessionStatus, TrustedPhoneNumber,
+    VerificationMode,
+};
+pub use client::{Client, ClientBuilder};
+pub use endpoint::Endpoints;
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use hyper::Uri;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// A cookie stored with the attributes needed to decide where it is sent.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    pub host_only: bool,
+    pub path: String,
+    pub expires: Option<DateTime<Utc>>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub created: DateTime<Utc>,
+}
+
+impl Cookie {
+    // Parses a `Set-Cookie` header value received in response to `uri`.
+    pub fn parse(header: &str, uri: &Uri) -> Option<Cookie> {
+        let now = Utc::now();
+        let host = uri.host()?.to_ascii_lowercase();
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: String::from(name),
+            value: String::from(value.trim()),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(uri.path()),
+            expires: None,
+            secure: false,
+            http_only: false,
+            created: now,
+        };
+        let mut max_age = None;
+
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain_match(&host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => {
+                    cookie.path = String::from(value);
+                }
+                "expires" => {
+                    if let Some(expires) = parse_date(value) {
+                        cookie.expires = Some(expires);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = value.parse::<i64>() {
+                        max_age = Some(now + Duration::seconds(seconds.max(0)));
+                    }
+                }
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+
+        // Max-Age takes precedence over Expires.
+        if max_age.is_some() {
+            cookie.expires = max_age;
+        }
+
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self, now: &DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= *now)
+    }
+
+    // Whether this cookie should be sent with a request to `uri`.
+    pub fn matches(&self, uri: &Uri) -> bool {
+        let host = match uri.host() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+        let host_matches = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        let secure_matches = !self.secure || uri.scheme_str() == Some("https");
+        host_matches && secure_matches && path_match(uri.path(), &self.path)
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    let request_path = if request_path.is_empty() { "/" } else { request_path };
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(index) if index > 0 => String::from(&request_path[..index]),
+        _ => String::from("/"),
+    }
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+    ["%a, %d-%b-%Y %H:%M:%S GMT", "%a, %d %b %Y %H:%M:%S GMT", "%A, %d-%b-%y %H:%M:%S GMT"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|date| Utc.from_utc_datetime(&date))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredCookies {
+    Jar { cookies: Vec<Cookie> },
+    Legacy(#[allow(dead_code)] BTreeMap<String, IgnoredAny>),
+}
+
+// A cookie store following the storage and retrieval rules of RFC 6265.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(from = "StoredCookies")]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl From<StoredCookies> for CookieJar {
+    fn from(stored: StoredCookies) -> CookieJar {
+        match stored {
+            StoredCookies::Jar { cookies } => CookieJar { cookies },
+            // Cookies saved before attributes were tracked are dropped, since
+            // there is no way to know which hosts they belong to.
+            StoredCookies::Legacy(_) => CookieJar::default(),
+        }
+    }
+}
+
+impl CookieJar {
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    // Stores a cookie from a `Set-Cookie` header, replacing any cookie with
+    // the same name, domain and path. Expired cookies are removed.
+    pub fn store(&mut self, header: &str, uri: &Uri) {
+        if let Some(mut cookie) = Cookie::parse(header, uri) {
+            let now = Utc::now();
+            if let Some(index) = self.cookies.iter().position(|existing| {
+                existing.name == cookie.name
+                    && existing.domain == cookie.domain
+                    && existing.path == cookie.path
+            }) {
+                cookie.created = self.cookies.remove(index).created;
+            }
+            if !cookie.is_expired(&now) {
+                self.cookies.push(cookie);
+            }
+        }
+    }
+
+    // Builds the `Cookie` header value for a request to `uri`.
+    pub fn header(&mut self, uri: &Uri) -> Option<String> {
+        let now = Utc::now();
+        self.cookies.retain(|cookie| !cookie.is_expired(&now));
+
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(uri))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created.cmp(&b.created))
+        });
+        Some(
+            matching
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<String>>()
+                .join("; "),
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.cookies.clear();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Cookie> {
+        self.cookies.iter()
+    }
+}
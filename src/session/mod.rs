@@ -1,18 +1,21 @@
 use std::collections::BTreeMap;
-use hyper::body::Buf;
-use hyper::client::HttpConnector;
-use hyper::{Body, Client, Method, Request, Response, StatusCode};
-use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper::Uri;
+use hyper::body::{Buf, Bytes};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::sync::Arc;
+use futures::lock::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+mod cookie;
+pub(crate) mod srp;
 mod uuid;
+use crate::account::DsInfo;
+use crate::endpoint::Endpoints;
 use crate::error::Error;
-
-const GLOBAL_HEADERS: [(&'static str, &'static str); 2] = [
-    ("Origin", "https://www.icloud.com"),
-    ("Referer", "https://www.icloud.com/"),
-];
+use crate::transport::Transport;
+pub use cookie::{Cookie, CookieJar};
+use srp::{SrpChallenge, SrpClient, PROTOCOLS};
 
 static ACCOUNT_COUNTRY_HEADER: &str = "X-Apple-ID-Account-Country";
 static SCNT_HEADER: &str = "scnt";
@@ -20,20 +23,18 @@ static SESSION_TOKEN_HEADER: &str = "X-Apple-Session-Token";
 static SESSION_ID_HEADER: &str = "X-Apple-ID-Session-Id";
 static TRUST_TOKEN_HEADER: &str = "X-Apple-TwoSV-Trust-Token";
 
-static OAUTH_STATE_HEADER: &'static str = "X-Apple-OAuth-State";
+static OAUTH_STATE_HEADER: &str = "X-Apple-OAuth-State";
 
-static AUTH_ENDPOINT: &'static str = "https://idmsa.apple.com/appleauth/auth";
-static SETUP_ENDPOINT: &'static str = "https://setup.icloud.com/setup/ws/1";
+static REDIRECT_URI_HEADER: &str = "X-Apple-OAuth-Redirect-URI";
 
 static APPLE_RESPONSE_HEADER: &str = "X-Apple-I-Rscd";
 
-const AUTH_HEADERS: [(&'static str, &'static str); 7] = [
+const AUTH_HEADERS: [(&str, &str); 6] = [
     (
         "X-Apple-OAuth-Client-Id",
         "d39ba9916b7251055b22c7f910e2ea796ee65e98b2ddecea8f5dde8d9d1a815d",
         ),
         ("X-Apple-OAuth-Client-Type", "firstPartyAuth"),
-        ("X-Apple-OAuth-Redirect-URI", "https://www.icloud.com"),
         ("X-Apple-OAuth-Require-Grant-Code", "true"),
         ("X-Apple-OAuth-Response-Mode", "web_message"),
         ("X-Apple-OAuth-Response-Type", "code"),
@@ -43,9 +44,95 @@ const AUTH_HEADERS: [(&'static str, &'static str); 7] = [
             ),
 ];
 
-#[derive(Serialize, Deserialize, Clone)]
+// A phone number that can receive two-factor authentication codes.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedPhoneNumber {
+    pub id: u32,
+    #[serde(default)]
+    pub number_with_dial_code: Option<String>,
+    pub obfuscated_number: String,
+    #[serde(default)]
+    pub push_mode: Option<String>,
+}
+
+// How a two-factor authentication code is delivered to a phone number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerificationMode {
+    Sms,
+    Voice,
+}
+
+impl VerificationMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VerificationMode::Sms => "sms",
+            VerificationMode::Voice => "voice",
+        }
+    }
+}
+
+// An iCloud web service advertised by `accountLogin`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct ServiceInfo {
+    #[serde(default)]
     pub url: String,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub pcs_required: Option<bool>,
+}
+
+impl ServiceInfo {
+    // Whether the service is usable; services without a status are
+    // assumed to be.
+    pub fn is_available(&self) -> bool {
+        self.status.as_deref().is_none_or(|status| status == "active")
+    }
+}
+
+// The state of a saved session as reported by `validate`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionStatus {
+    // Whether the server still accepts the session.
+    pub live: bool,
+    // Whether a second factor must be provided before the session can be
+    // used.
+    pub needs_2fa: bool,
+}
+
+// The iCloud web services known to this crate, named by their key in the
+// `webservices` map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Service {
+    Drive,
+    Documents,
+    CloudKit,
+    Contacts,
+    Calendar,
+    Reminders,
+    FindMe,
+    Photos,
+    Account,
+    Ubiquity,
+}
+
+impl Service {
+    pub fn key(&self) -> &'static str {
+        match self {
+            Service::Drive => "drivews",
+            Service::Documents => "docws",
+            Service::CloudKit => "ckdatabasews",
+            Service::Contacts => "contacts",
+            Service::Calendar => "calendar",
+            Service::Reminders => "reminders",
+            Service::FindMe => "findme",
+            Service::Photos => "photos",
+            Service::Account => "account",
+            Service::Ubiquity => "ubiquity",
+        }
+    }
 }
 
 // Session data for authenticating and accessing iCloud.
@@ -57,8 +144,10 @@ pub struct SessionData {
     trust_token: Option<String>,
     scnt: Option<String>,
     account_country: Option<String>,
-    cookies: BTreeMap<String, String>,
+    cookies: CookieJar,
     webservices: BTreeMap<String, ServiceInfo>,
+    #[serde(default)]
+    ds_info: Option<DsInfo>,
 }
 
 impl SessionData {
@@ -70,111 +159,362 @@ impl SessionData {
             trust_token: None,
             scnt: None,
             account_country: None,
-            cookies: BTreeMap::new(),
+            cookies: CookieJar::new(),
             webservices: BTreeMap::new(),
+            ds_info: None,
         })
     }
+
+    // The cookies stored for this session.
+    pub fn cookies(&self) -> &CookieJar {
+        &self.cookies
+    }
+
+    // Forgets every token, cookie and service learned from the server.
+    pub fn clear(&mut self) {
+        self.session_id = None;
+        self.session_token = None;
+        self.trust_token = None;
+        self.scnt = None;
+        self.account_country = None;
+        self.cookies.clear();
+        self.webservices.clear();
+        self.ds_info = None;
+    }
+
+    // The account owner's identity from the last `accountLogin`.
+    pub fn ds_info(&self) -> Option<&DsInfo> {
+        self.ds_info.as_ref()
+    }
 }
 
 pub struct Session {
-    client: Client<HttpsConnector<HttpConnector>, Body>,
+    transport: Arc<dyn Transport>,
     data: SessionData,
+    endpoints: Endpoints,
+    // How many times the session was renewed after expiring, so that
+    // concurrent requests which all found it expired renew it only once.
+    renewals: u64,
 }
 
 impl Session {
-    pub fn new(data: SessionData) -> Result<Session, Error> {
+    pub fn new(
+        data: SessionData,
+        endpoints: Endpoints,
+        transport: Arc<dyn Transport>,
+    ) -> Result<Session, Error> {
         Ok(Session {
-            client: Client::builder().build(
-                        HttpsConnectorBuilder::new()
-                        .with_native_roots()
-                        .https_only()
-                        .enable_http1()
-                        .build(),
-                        ),
-                        data: data,
+            transport,
+            data,
+            endpoints,
+            renewals: 0,
         })
     }
 
+    // Sends a request, re-authenticating and replaying it once if the
+    // session has expired.
     pub async fn request<F>(
         &mut self,
         method: Method,
         uri: String,
-        body: Body,
+        body: Bytes,
         f: F,
         ) -> Result<Response<Body>, Error>
         where
-        F: FnOnce(&mut http::request::Builder) -> Result<(), Error>,
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
         {
-            let mut request_builder = Request::builder().method(method).uri(uri);
-
-            request_builder = request_builder.header(
-                &String::from(OAUTH_STATE_HEADER),
-                self.data.oauth_state.clone(),
-                );
+            let response = self.send(method.clone(), uri.clone(), body.clone(), &f).await?;
+            let response = if Session::is_expired(&response) {
+                self.authenticate().await?;
+                self.renewals += 1;
+                self.send(method, uri, body, &f).await?
+            } else {
+                response
+            };
 
-            if let Some(session_id) = &self.data.session_id {
-                request_builder = request_builder.header(&String::from(SESSION_ID_HEADER), session_id);
+            if Session::is_expired(&response) {
+                Err(Error::AuthenticationFailed(String::from("Unauthorized request")))
+            } else {
+                Ok(response)
             }
+        }
 
-            if let Some(scnt) = &self.data.scnt {
-                request_builder = request_builder.header(&String::from(SCNT_HEADER), scnt);
+    // Sends a request like `request`, but holds the lock on the session only
+    // while the request is built and while the response's headers are taken
+    // in, so that several requests can be in flight at once.
+    pub async fn request_shared<F>(
+        session: &Mutex<Session>,
+        method: Method,
+        uri: String,
+        body: Bytes,
+        f: F,
+    ) -> Result<Response<Body>, Error>
+    where
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
+    {
+        let (response, renewals) =
+            Session::send_shared(session, method.clone(), uri.clone(), Body::from(body.clone()), &f).await?;
+        let response = if Session::is_expired(&response) {
+            let mut session = session.lock().await;
+            // Another request may have renewed the session meanwhile.
+            if session.renewals == renewals {
+                session.authenticate().await?;
+                session.renewals += 1;
             }
+            session.send(method, uri, body, &f).await?
+        } else {
+            response
+        };
 
-            for (key, value) in GLOBAL_HEADERS {
-                request_builder = request_builder.header(key, value);
-            }
+        if Session::is_expired(&response) {
+            Err(Error::AuthenticationFailed(String::from("Unauthorized request")))
+        } else {
+            Ok(response)
+        }
+    }
 
-            if self.data.cookies.len() > 0 {
-                let cookies: Vec<String> = self.data.cookies.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
-                request_builder =
-                    request_builder.header(hyper::header::COOKIE, cookies.as_slice().join("; "));
-            }
+    // Sends a request without holding the lock on the session while it is
+    // in flight. Returns the response along with the number of renewals
+    // when the request was built.
+    async fn send_shared<F>(
+        session: &Mutex<Session>,
+        method: Method,
+        uri: String,
+        body: Body,
+        f: F,
+    ) -> Result<(Response<Body>, u64), Error>
+    where
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
+    {
+        let uri: Uri = uri.parse()?;
+        let (transport, request, renewals) = {
+            let mut session = session.lock().await;
+            let request = session.prepare(method, &uri, body, f)?;
+            (session.transport.clone(), request, session.renewals)
+        };
+        let response = transport.send(request).await?;
+        session.lock().await.absorb(&response, &uri)?;
+        Ok((response, renewals))
+    }
 
-            f(&mut request_builder)?;
+    fn is_expired(response: &Response<Body>) -> bool {
+        matches!(response.status().as_u16(), 401 | 421 | 450)
+    }
 
-            match self.client.request(request_builder.body(body)?).await {
-                Ok(response) => {
-                    if let Some(account_country) = response.headers().get(ACCOUNT_COUNTRY_HEADER) {
-                        self.data.account_country = Some(String::from(account_country.to_str()?));
-                    }
+    // Sends a request with a streamed body, holding the lock on the session
+    // only while the request is built and while the response's headers are
+    // taken in, so that other requests can go through while the body is
+    // sent. Streamed bodies cannot be replayed, so an expired session is
+    // reported rather than renewed.
+    pub async fn request_streaming<F>(
+        session: &Mutex<Session>,
+        method: Method,
+        uri: String,
+        body: Body,
+        f: F,
+    ) -> Result<Response<Body>, Error>
+    where
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
+    {
+        let (response, _) = Session::send_shared(session, method, uri, body, f).await?;
+        if Session::is_expired(&response) {
+            Err(Error::AuthenticationFailed(String::from("Unauthorized request")))
+        } else {
+            Ok(response)
+        }
+    }
 
-                    if let Some(session_id) = response.headers().get(SESSION_ID_HEADER) {
-                        self.data.session_id = Some(String::from(session_id.to_str()?));
-                    }
+    async fn send<F>(
+        &mut self,
+        method: Method,
+        uri: String,
+        body: Bytes,
+        f: F,
+        ) -> Result<Response<Body>, Error>
+        where
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
+        {
+            self.send_body(method, uri, Body::from(body), f).await
+        }
 
-                    if let Some(session_token) = response.headers().get(SESSION_TOKEN_HEADER) {
-                        self.data.session_token = Some(String::from(session_token.to_str()?));
-                    }
+    async fn send_body<F>(
+        &mut self,
+        method: Method,
+        uri: String,
+        body: Body,
+        f: F,
+        ) -> Result<Response<Body>, Error>
+        where
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
+        {
+            let uri: Uri = uri.parse()?;
+            let request = self.prepare(method, &uri, body, f)?;
+            let response = self.transport.send(request).await?;
+            self.absorb(&response, &uri)?;
+            Ok(response)
+        }
 
-                    if let Some(scnt) = response.headers().get(SCNT_HEADER) {
-                        self.data.scnt = Some(String::from(scnt.to_str()?));
-                    }
+    // Builds a request carrying the session's headers and cookies.
+    fn prepare<F>(&mut self, method: Method, uri: &Uri, body: Body, f: F) -> Result<Request<Body>, Error>
+    where
+        F: Fn(&mut http::request::Builder) -> Result<(), Error>,
+    {
+        let mut request_builder = Request::builder().method(method).uri(uri.clone());
 
-                    if let Some(trust_token) = response.headers().get(TRUST_TOKEN_HEADER) {
-                        self.data.trust_token = Some(String::from(trust_token.to_str()?));
-                    }
+        request_builder = request_builder.header(
+            &String::from(OAUTH_STATE_HEADER),
+            self.data.oauth_state.clone(),
+        );
 
-                    for (key, value) in response.headers() {
-                        if key == hyper::header::SET_COOKIE {
-                            if let Some(cookie) = value.to_str()?.split(";").next() {
-                                if let Some((key, value)) = cookie.split_once("=") {
-                                    self.data.cookies.insert(String::from(key), String::from(value));
+        if let Some(session_id) = &self.data.session_id {
+            request_builder = request_builder.header(&String::from(SESSION_ID_HEADER), session_id);
+        }
 
-                                }
-                            }
-                        }
-                    }
+        if let Some(scnt) = &self.data.scnt {
+            request_builder = request_builder.header(&String::from(SCNT_HEADER), scnt);
+        }
+
+        request_builder = request_builder
+            .header("Origin", self.endpoints.home.as_str())
+            .header("Referer", format!("{}/", self.endpoints.home));
+
+        if uri.to_string().starts_with(&self.endpoints.auth) {
+            request_builder = request_builder.header(REDIRECT_URI_HEADER, self.endpoints.home.as_str());
+        }
 
-                    match response.status() {
-                        StatusCode::UNAUTHORIZED => Err(Error::AuthenticationFailed(String::from("Unauthorized request"))),
-                        _ => Ok(response),
+        if let Some(cookies) = self.data.cookies.header(uri) {
+            request_builder = request_builder.header(hyper::header::COOKIE, cookies);
+        }
+
+        f(&mut request_builder)?;
+
+        Ok(request_builder.body(body)?)
+    }
+
+    // Takes in the session state a response hands back in its headers.
+    fn absorb(&mut self, response: &Response<Body>, uri: &Uri) -> Result<(), Error> {
+        if let Some(account_country) = response.headers().get(ACCOUNT_COUNTRY_HEADER) {
+            self.data.account_country = Some(String::from(account_country.to_str()?));
+        }
+
+        if let Some(session_id) = response.headers().get(SESSION_ID_HEADER) {
+            self.data.session_id = Some(String::from(session_id.to_str()?));
+        }
+
+        if let Some(session_token) = response.headers().get(SESSION_TOKEN_HEADER) {
+            self.data.session_token = Some(String::from(session_token.to_str()?));
+        }
+
+        if let Some(scnt) = response.headers().get(SCNT_HEADER) {
+            self.data.scnt = Some(String::from(scnt.to_str()?));
+        }
+
+        if let Some(trust_token) = response.headers().get(TRUST_TOKEN_HEADER) {
+            self.data.trust_token = Some(String::from(trust_token.to_str()?));
+        }
+
+        for value in response.headers().get_all(hyper::header::SET_COOKIE) {
+            self.data.cookies.store(value.to_str()?, uri);
+        }
+
+        Ok(())
+    }
+
+    // Logs in using the SRP-6a handshake, falling back to sending the
+    // password directly when the server does not offer SRP.
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        let client = SrpClient::new();
+        match self.signin_init(username, &client).await? {
+            Some(challenge) => {
+                self.signin_complete(username, password, &client, &challenge)
+                    .await
+            }
+            None => self.login_plaintext(username, password).await,
+        }
+    }
+
+    async fn signin_init(
+        &mut self,
+        username: &str,
+        client: &SrpClient,
+    ) -> Result<Option<SrpChallenge>, Error> {
+        let body = json!({
+            "a": client.public_key(),
+            "accountName": username,
+            "protocols": PROTOCOLS,
+        })
+        .to_string();
+
+        let uri = format!("{}/signin/init", self.endpoints.auth);
+
+        let response = self
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                    for (key, value) in AUTH_HEADERS {
+                        headers.insert(key, value.parse()?);
                     }
                 }
-                Err(err) => Err(Error::from(err)),
+                Ok(())
+            })
+        .await?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let body = hyper::body::aggregate(response).await?;
+                Ok(Some(serde_json::from_reader(body.reader())?))
             }
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+                Ok(None)
+            }
+            _ => Err(Error::InvalidCredentials),
         }
+    }
+
+    async fn signin_complete(
+        &mut self,
+        username: &str,
+        password: &str,
+        client: &SrpClient,
+        challenge: &SrpChallenge,
+    ) -> Result<(), Error> {
+        let proof = client.process_challenge(username, password, challenge)?;
 
-    pub async fn login(&mut self, username: &str, password: &str) -> Result<(), Error> {
+        let body = json!({
+            "accountName": username,
+            "c": challenge.c,
+            "m1": proof.m1,
+            "m2": proof.m2,
+            "rememberMe": true,
+            "trustTokens": self.data.trust_token.iter().collect::<Vec<_>>(),
+        })
+        .to_string();
+
+        let uri = format!("{}/signin/complete?isRememberMeEnabled=true", self.endpoints.auth);
+
+        let response = self
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                    for (key, value) in AUTH_HEADERS {
+                        headers.insert(key, value.parse()?);
+                    }
+                }
+                Ok(())
+            })
+        .await?;
+
+        // A 409 means the credentials were accepted but a second factor is
+        // still required, which `authenticate` reports as `Needs2FA`.
+        match response.status() {
+            StatusCode::OK | StatusCode::CONFLICT => self.authenticate().await,
+            _ => Err(Error::InvalidCredentials),
+        }
+    }
+
+    async fn login_plaintext(&mut self, username: &str, password: &str) -> Result<(), Error> {
         let body = json!({
             "accountName" : username,
             "password" : password,
@@ -183,10 +523,10 @@ impl Session {
         })
         .to_string();
 
-        let uri = format!("{}/signin?isRememberMeEnable=true", AUTH_ENDPOINT);
+        let uri = format!("{}/signin?isRememberMeEnable=true", self.endpoints.auth);
 
         let response = self
-            .request(Method::POST, uri, Body::from(body), |builder| {
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
                 if let Some(headers) = builder.headers_mut() {
                     headers.insert("Content-Type", "application/json".parse()?);
                     headers.insert("Accept", "*/*".parse()?);
@@ -199,8 +539,11 @@ impl Session {
         .await?;
 
         if response.status() == StatusCode::OK {
+            // The status Apple means is in a header: 409 when a second
+            // factor is still required, like `signin/complete`.
             if let Some(rscd) = response.headers().get(APPLE_RESPONSE_HEADER) {
-                if StatusCode::from_bytes(rscd.as_bytes())? != StatusCode::CONFLICT {
+                let status = StatusCode::from_bytes(rscd.as_bytes())?;
+                if status != StatusCode::OK && status != StatusCode::CONFLICT {
                     return Err(Error::InvalidCredentials);
                 }
             }
@@ -221,10 +564,10 @@ impl Session {
         })
         .to_string();
 
-        let uri = format!("{}/accountLogin", SETUP_ENDPOINT);
+        let uri = format!("{}/accountLogin", self.endpoints.setup);
 
         let response = self
-            .request(Method::POST, uri, Body::from(body), |builder| {
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
                 if let Some(headers) = builder.headers_mut() {
                     headers.insert("Content-Type", "application/json".parse()?);
                     headers.insert("Accept", "*/*".parse()?);
@@ -240,13 +583,12 @@ impl Session {
             let body = hyper::body::aggregate(response).await?;
             let auth_info: serde_json::Value = serde_json::from_reader(body.reader())?;
 
-            if let Some(drivews_url) = auth_info["webservices"]["drivews"]["url"].as_str() {
-                self.data.webservices.insert(
-                    String::from("drive"),
-                    ServiceInfo {
-                        url: drivews_url.to_string(),
-                    },
-                    );
+            if let Some(webservices) = auth_info.get("webservices") {
+                self.data.webservices = serde_json::from_value(webservices.clone())?;
+            }
+
+            if let Some(ds_info) = auth_info.get("dsInfo") {
+                self.data.ds_info = Some(serde_json::from_value(ds_info.clone())?);
             }
 
             if auth_info["hsaChallengeRequired"] == true {
@@ -264,10 +606,10 @@ impl Session {
     }
 
     pub async fn trust_session(&mut self) -> Result<(), Error> {
-        let uri = format!("{}/2sv/trust", AUTH_ENDPOINT);
+        let uri = format!("{}/2sv/trust", self.endpoints.auth);
 
         let response = self
-            .request(Method::GET, uri, Body::empty(), |builder| {
+            .send(Method::GET, uri, Bytes::new(), |builder| {
                 if let Some(headers) = builder.headers_mut() {
                     headers.insert("Accept", "*/*".parse()?);
                     for (key, value) in AUTH_HEADERS {
@@ -286,7 +628,7 @@ impl Session {
     }
 
     pub async fn authenticate_2fa(&mut self, code: &str) -> Result<(), Error> {
-        let uri = format!("{}/verify/trusteddevice/securitycode", AUTH_ENDPOINT);
+        let uri = format!("{}/verify/trusteddevice/securitycode", self.endpoints.auth);
 
         let body = json!({
             "securityCode": {
@@ -296,7 +638,7 @@ impl Session {
         .to_string();
 
         let response = self
-            .request(Method::POST, uri, Body::from(body), |builder| {
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
                 if let Some(headers) = builder.headers_mut() {
                     headers.insert("Content-Type", "application/json".parse()?);
                     headers.insert("Accept", "application/json".parse()?);
@@ -315,11 +657,196 @@ impl Session {
         }
     }
 
-    pub fn get_service_info(&self, name: String) -> Option<&ServiceInfo> {
-        self.data.webservices.get(&name)
+    // Retrieves the phone numbers that can receive verification codes.
+    pub async fn trusted_phone_numbers(&mut self) -> Result<Vec<TrustedPhoneNumber>, Error> {
+        let uri = self.endpoints.auth.clone();
+
+        let response = self
+            .send(Method::GET, uri, Bytes::new(), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Accept", "application/json".parse()?);
+                    for (key, value) in AUTH_HEADERS {
+                        headers.insert(key, value.parse()?);
+                    }
+                }
+                Ok(())
+            })
+        .await?;
+
+        if response.status() == StatusCode::OK {
+            let body = hyper::body::aggregate(response).await?;
+            let auth_info: serde_json::Value = serde_json::from_reader(body.reader())?;
+            match auth_info.get("trustedPhoneNumbers") {
+                Some(numbers) => Ok(serde_json::from_value(numbers.clone())?),
+                None => Ok(Vec::new()),
+            }
+        } else {
+            Err(Error::AuthenticationFailed(String::from("Unable to list trusted phone numbers")))
+        }
+    }
+
+    // Asks Apple to send a verification code to a trusted phone number.
+    pub async fn request_phone_code(
+        &mut self,
+        phone: &TrustedPhoneNumber,
+        mode: VerificationMode,
+    ) -> Result<(), Error> {
+        let uri = format!("{}/verify/phone", self.endpoints.auth);
+
+        let body = json!({
+            "phoneNumber": {
+                "id": phone.id
+            },
+            "mode": mode.as_str()
+        })
+        .to_string();
+
+        let response = self
+            .send(Method::PUT, uri, Bytes::from(body), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                    for (key, value) in AUTH_HEADERS {
+                        headers.insert(key, value.parse()?);
+                    }
+                }
+                Ok(())
+            })
+        .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::AuthenticationFailed(String::from("Unable to send verification code")))
+        }
+    }
+
+    // Authenticates with a code delivered to a trusted phone number.
+    pub async fn authenticate_phone_2fa(
+        &mut self,
+        phone: &TrustedPhoneNumber,
+        mode: VerificationMode,
+        code: &str,
+    ) -> Result<(), Error> {
+        let uri = format!("{}/verify/phone/securitycode", self.endpoints.auth);
+
+        let body = json!({
+            "phoneNumber": {
+                "id": phone.id
+            },
+            "securityCode": {
+                "code": code
+            },
+            "mode": mode.as_str()
+        })
+        .to_string();
+
+        let response = self
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                    for (key, value) in AUTH_HEADERS {
+                        headers.insert(key, value.parse()?);
+                    }
+                }
+                Ok(())
+            })
+        .await?;
+
+        if response.status().is_success() {
+            self.trust_session().await
+        } else {
+            Err(Error::AuthenticationFailed(String::from("Invalid 2FA code")))
+        }
+    }
+
+    // Checks whether the session is still accepted without attempting to
+    // re-authenticate.
+    pub async fn validate(&mut self) -> Result<SessionStatus, Error> {
+        let uri = format!("{}/validate", self.endpoints.setup);
+
+        let response = self
+            .send(Method::POST, uri, Bytes::from("null"), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+        .await?;
+
+        if response.status() == StatusCode::OK {
+            let body = hyper::body::aggregate(response).await?;
+            let info: serde_json::Value = serde_json::from_reader(body.reader())?;
+            Ok(SessionStatus {
+                live: true,
+                needs_2fa: info["hsaChallengeRequired"] == true && info["hsaTrustedBrowser"] != true,
+            })
+        } else if Session::is_expired(&response) {
+            Ok(SessionStatus {
+                live: false,
+                needs_2fa: false,
+            })
+        } else {
+            Err(Error::UnexpectedStatus(response.status()))
+        }
+    }
+
+    // Signs out, asking the server to forget this browser's trust, and
+    // clears the local tokens and cookies. The local state is cleared even
+    // if the server rejects the request.
+    pub async fn logout(&mut self) -> Result<(), Error> {
+        let uri = format!("{}/logout", self.endpoints.setup);
+
+        let body = json!({
+            "trustBrowsers": false,
+            "allBrowsers": false
+        })
+        .to_string();
+
+        let result = self
+            .send(Method::POST, uri, Bytes::from(body), |builder| {
+                if let Some(headers) = builder.headers_mut() {
+                    headers.insert("Content-Type", "application/json".parse()?);
+                    headers.insert("Accept", "application/json".parse()?);
+                }
+                Ok(())
+            })
+            .await;
+
+        self.data.clear();
+
+        let response = result?;
+        if response.status().is_success() || Session::is_expired(&response) {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedStatus(response.status()))
+        }
+    }
+
+    pub fn get_service_info(&self, name: &str) -> Option<&ServiceInfo> {
+        self.data.webservices.get(name)
+    }
+
+    // Looks up a service, failing if it is missing or marked unavailable.
+    pub fn service(&self, service: Service) -> Result<&ServiceInfo, Error> {
+        match self.get_service_info(service.key()) {
+            Some(info) if info.is_available() => Ok(info),
+            Some(_) => Err(Error::ServiceUnavailable(String::from(service.key()))),
+            None => Err(Error::MissingService(String::from(service.key()))),
+        }
+    }
+
+    pub fn services(&self) -> &BTreeMap<String, ServiceInfo> {
+        &self.data.webservices
     }
 
     pub fn data(&self) -> &SessionData {
         &self.data
     }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
 }
@@ -0,0 +1,283 @@
+use crate::error::Error;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use num_bigint::BigUint;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// The 2048-bit group from RFC 5054, which is the group Apple's
+// web client negotiates.
+static N_HEX: &[u8] = b"AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050\
+A37329CBB4A099ED8193E0757767A13DD52312AB4B03310DCD7F48A9DA04FD50\
+E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9962F0B93B8\
+55F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773B\
+CA97B43A23FB801676BD207A436C6481F1D2B9078717461A5B9D32E688F87748\
+544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52FB3786160279004E57AE6\
+AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB6\
+94B5C803D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73";
+
+const G: u32 = 2;
+
+// The password derivation protocols offered to the server.
+pub const PROTOCOLS: [&str; 2] = ["s2k", "s2k_fo"];
+
+// The server's reply to `signin/init`.
+#[derive(Deserialize)]
+pub struct SrpChallenge {
+    pub iteration: u32,
+    pub salt: String,
+    pub protocol: String,
+    pub b: String,
+    pub c: String,
+}
+
+// The client proofs sent to `signin/complete`.
+pub struct SrpProof {
+    pub m1: String,
+    pub m2: String,
+}
+
+// The client side of an SRP-6a handshake using SHA-256.
+pub struct SrpClient {
+    n: BigUint,
+    g: BigUint,
+    a: BigUint,
+    a_pub: BigUint,
+}
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn pad(value: &BigUint, width: usize) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut padded = vec![0; width.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+// Derives the SRP password from the account password as described by
+// the negotiated protocol.
+fn derive_password(
+    password: &str,
+    salt: &[u8],
+    iterations: u32,
+    protocol: &str,
+) -> Result<Vec<u8>, Error> {
+    let digest = Sha256::digest(password.as_bytes());
+    let input = match protocol {
+        "s2k" => digest.to_vec(),
+        "s2k_fo" => digest
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+            .into_bytes(),
+        _ => return Err(Error::UnsupportedProtocol(String::from(protocol))),
+    };
+    let mut derived = vec![0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(&input, salt, iterations, &mut derived);
+    Ok(derived)
+}
+
+fn group() -> (BigUint, BigUint) {
+    let n = BigUint::parse_bytes(N_HEX, 16).expect("invalid SRP group");
+    (n, BigUint::from(G))
+}
+
+fn compute_k(n: &BigUint, g: &BigUint) -> BigUint {
+    let width = n.to_bytes_be().len();
+    BigUint::from_bytes_be(&hash(&[&n.to_bytes_be(), &pad(g, width)]))
+}
+
+fn compute_u(n: &BigUint, a_pub: &BigUint, b_pub: &BigUint) -> BigUint {
+    let width = n.to_bytes_be().len();
+    BigUint::from_bytes_be(&hash(&[&pad(a_pub, width), &pad(b_pub, width)]))
+}
+
+fn compute_x(salt: &[u8], password: &[u8]) -> BigUint {
+    BigUint::from_bytes_be(&hash(&[salt, &hash(&[b":", password])]))
+}
+
+fn compute_m1(
+    n: &BigUint,
+    g: &BigUint,
+    username: &str,
+    salt: &[u8],
+    a_pub: &BigUint,
+    b_pub: &BigUint,
+    key: &[u8],
+) -> Vec<u8> {
+    let width = n.to_bytes_be().len();
+    let h_n = hash(&[&n.to_bytes_be()]);
+    let h_g = hash(&[&pad(g, width)]);
+    let h_ng: Vec<u8> = h_n.iter().zip(h_g.iter()).map(|(n, g)| n ^ g).collect();
+    hash(&[
+        &h_ng,
+        &hash(&[username.as_bytes()]),
+        salt,
+        &a_pub.to_bytes_be(),
+        &b_pub.to_bytes_be(),
+        key,
+    ])
+}
+
+fn compute_m2(a_pub: &BigUint, m1: &[u8], key: &[u8]) -> Vec<u8> {
+    hash(&[&a_pub.to_bytes_be(), m1, key])
+}
+
+impl SrpClient {
+    pub fn new() -> SrpClient {
+        let mut secret = [0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        SrpClient::with_secret(&secret)
+    }
+
+    // Constructs a client with a fixed ephemeral secret.
+    pub fn with_secret(secret: &[u8]) -> SrpClient {
+        let (n, g) = group();
+        let a = BigUint::from_bytes_be(secret);
+        let a_pub = g.modpow(&a, &n);
+        SrpClient { n, g, a, a_pub }
+    }
+
+    // The client's public ephemeral value, base64 encoded.
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.a_pub.to_bytes_be())
+    }
+
+    // Computes the client proofs for the server's challenge.
+    pub fn process_challenge(
+        &self,
+        username: &str,
+        password: &str,
+        challenge: &SrpChallenge,
+    ) -> Result<SrpProof, Error> {
+        let salt = BASE64.decode(&challenge.salt)?;
+        let b_pub = BigUint::from_bytes_be(&BASE64.decode(&challenge.b)?);
+
+        if (&b_pub % &self.n) == BigUint::from(0u32) {
+            return Err(Error::AuthenticationFailed(String::from(
+                "Invalid SRP challenge",
+            )));
+        }
+
+        let password = derive_password(password, &salt, challenge.iteration, &challenge.protocol)?;
+
+        let k = compute_k(&self.n, &self.g);
+        let u = compute_u(&self.n, &self.a_pub, &b_pub);
+        let x = compute_x(&salt, &password);
+
+        let v = self.g.modpow(&x, &self.n);
+        let base = (&b_pub + &self.n - (k * v) % &self.n) % &self.n;
+        let s = base.modpow(&(&self.a + u * x), &self.n);
+        let key = hash(&[&s.to_bytes_be()]);
+
+        let m1 = compute_m1(&self.n, &self.g, username, &salt, &self.a_pub, &b_pub, &key);
+        let m2 = compute_m2(&self.a_pub, &m1, &key);
+
+        Ok(SrpProof {
+            m1: BASE64.encode(m1),
+            m2: BASE64.encode(m2),
+        })
+    }
+}
+
+// The server side of the handshake, used by the mock iCloud server and
+// the tests.
+#[cfg(any(test, feature = "testing"))]
+pub struct SrpServer {
+    n: BigUint,
+    g: BigUint,
+    salt: Vec<u8>,
+    v: BigUint,
+    b: BigUint,
+    b_pub: BigUint,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl SrpServer {
+    pub fn new(
+        password: &str,
+        salt: &[u8],
+        iterations: u32,
+        protocol: &str,
+    ) -> Result<SrpServer, Error> {
+        let (n, g) = group();
+        let derived = derive_password(password, salt, iterations, protocol)?;
+        let v = g.modpow(&compute_x(salt, &derived), &n);
+        let mut secret = [0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let b = BigUint::from_bytes_be(&secret);
+        let b_pub = (compute_k(&n, &g) * &v + g.modpow(&b, &n)) % &n;
+        Ok(SrpServer {
+            n,
+            g,
+            salt: salt.to_vec(),
+            v,
+            b,
+            b_pub,
+        })
+    }
+
+    // The server's public ephemeral value, base64 encoded.
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.b_pub.to_bytes_be())
+    }
+
+    // Checks the client's proof against the client's public value.
+    pub fn verify(&self, username: &str, a: &str, m1: &str) -> Result<bool, Error> {
+        let a_pub = BigUint::from_bytes_be(&BASE64.decode(a)?);
+        if (&a_pub % &self.n) == BigUint::from(0u32) {
+            return Ok(false);
+        }
+        let u = compute_u(&self.n, &a_pub, &self.b_pub);
+        let s = (a_pub.clone() * self.v.modpow(&u, &self.n)).modpow(&self.b, &self.n);
+        let key = hash(&[&s.to_bytes_be()]);
+        let expected = compute_m1(&self.n, &self.g, username, &self.salt, &a_pub, &self.b_pub, &key);
+        Ok(BASE64.encode(expected) == m1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(server: &SrpServer, salt: &[u8], protocol: &str) -> SrpChallenge {
+        SrpChallenge {
+            iteration: 1000,
+            salt: BASE64.encode(salt),
+            protocol: String::from(protocol),
+            b: server.public_key(),
+            c: String::from("c"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let salt = b"0123456789abcdef";
+        for protocol in PROTOCOLS {
+            let server = SrpServer::new("hunter2", salt, 1000, protocol).unwrap();
+            let client = SrpClient::new();
+            let proof = client
+                .process_challenge("user@example.com", "hunter2", &challenge(&server, salt, protocol))
+                .unwrap();
+            assert!(server.verify("user@example.com", &client.public_key(), &proof.m1).unwrap());
+        }
+    }
+
+    #[test]
+    fn wrong_password() {
+        let salt = b"0123456789abcdef";
+        let server = SrpServer::new("hunter2", salt, 1000, "s2k").unwrap();
+        let client = SrpClient::new();
+        let proof = client
+            .process_challenge("user@example.com", "hunter3", &challenge(&server, salt, "s2k"))
+            .unwrap();
+        assert!(!server.verify("user@example.com", &client.public_key(), &proof.m1).unwrap());
+    }
+}
@@ -0,0 +1,33 @@
+use crate::error::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+// The most items a long run handles between saves of its state, which
+// bounds the work an interruption loses.
+pub(crate) const SAVE_INTERVAL: usize = 25;
+
+// Reads a value saved by `save_json`, or the default if there is none yet.
+pub(crate) fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T, Error> {
+    if path.exists() {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    } else {
+        Ok(T::default())
+    }
+}
+
+// Writes a value to a temporary file and renames it into place, so an
+// interrupted save leaves the previous file intact.
+pub(crate) fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let temp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use crate::backup::{download_file, move_file};
+use crate::drive::{DriveNode, DriveService, File, Folder, WalkOptions};
+use crate::error::Error;
+use crate::store::SAVE_INTERVAL;
+use chrono::Utc;
+use futures::io::AllowStdIo;
+use futures::TryStreamExt;
+use plan::{local_item, rebase, scan_local, synced_entry, LocalItem, Step};
+use state::{SyncEntry, SyncState};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+mod plan;
+mod state;
+
+pub use plan::{SyncAction, SyncPlan};
+
+// The directory in the local directory holding the sync state, unless
+// another is given.
+static STATE_DIR: &str = ".icloud-sync";
+static STATE_FILE: &str = "state.json";
+static PARTIAL_DIR: &str = "partial";
+
+// The outcome of applying a sync plan.
+#[derive(Default, Debug)]
+pub struct SyncReport {
+    pub applied: Vec<SyncAction>,
+    pub failed: Vec<(SyncAction, Error)>,
+}
+
+// A two-way sync between a local directory and a folder on the drive.
+//
+// Each side is compared with the state saved by the last sync, so a change
+// on either side is carried to the other: creations, edits, moves and
+// renames, and deletions. When a file changed on both sides, the local
+// version is kept next to it as a conflicted copy rather than overwritten.
+// `plan` reports what a sync would do without doing it.
+pub struct SyncEngine {
+    local: PathBuf,
+    remote: Folder,
+    state_dir: PathBuf,
+}
+
+// The progress of a plan being applied.
+struct Progress {
+    state: SyncState,
+    folders: BTreeMap<PathBuf, Folder>,
+    // Local moves made so far, which later steps' local paths follow.
+    local_moves: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Progress {
+    fn folder(&self, path: Option<&Path>) -> Result<Folder, Error> {
+        let path = path.unwrap_or(Path::new(""));
+        self.folders
+            .get(path)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(path.display().to_string()))
+    }
+
+    fn local_path(&self, path: &Path) -> PathBuf {
+        rebase(path, &self.local_moves)
+    }
+}
+
+fn file_name(path: &Path) -> Result<String, Error> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| Error::InvalidSyncState(format!("No name in {}", path.display())))
+}
+
+fn remote_node(step: &Step) -> Result<&DriveNode, Error> {
+    step.remote
+        .as_ref()
+        .ok_or_else(|| Error::InvalidSyncState(format!("No drive item for {}", step.action)))
+}
+
+fn remote_file(step: &Step) -> Result<&File, Error> {
+    match remote_node(step)? {
+        DriveNode::File(file) => Ok(file),
+        _ => Err(Error::InvalidDriveNodeType),
+    }
+}
+
+fn local(step: &Step) -> Result<&LocalItem, Error> {
+    step.local
+        .as_ref()
+        .ok_or_else(|| Error::InvalidSyncState(format!("No local item for {}", step.action)))
+}
+
+impl SyncEngine {
+    // Syncs `local`, which is created if needed, with `remote`.
+    pub fn new<P: AsRef<Path>>(local: P, remote: &Folder) -> SyncEngine {
+        let local = local.as_ref().to_path_buf();
+        SyncEngine {
+            state_dir: local.join(STATE_DIR),
+            local,
+            remote: remote.clone(),
+        }
+    }
+
+    // Keeps the sync state in `dir` instead of inside the local directory.
+    pub fn with_state_dir<P: AsRef<Path>>(mut self, dir: P) -> SyncEngine {
+        self.state_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.state_dir.join(STATE_FILE)
+    }
+
+    fn load_state(&self) -> Result<SyncState, Error> {
+        let state = SyncState::load(&self.state_path())?;
+        match &state.root {
+            Some(root) if *root != self.remote.id => Err(Error::InvalidSyncState(format!(
+                "{} belongs to a sync with {}",
+                self.state_path().display(),
+                root
+            ))),
+            _ => Ok(state),
+        }
+    }
+
+    // Works out what a sync would do, without changing either side.
+    pub async fn plan(&self, drive: &mut DriveService) -> Result<SyncPlan, Error> {
+        let mut state = self.load_state()?;
+        std::fs::create_dir_all(&self.local)?;
+        drive.expire_cache()?;
+        let mut remote: Vec<(PathBuf, DriveNode)> = drive
+            .walk(&self.remote, WalkOptions::new())
+            .try_collect()
+            .await?;
+        let mut local = scan_local(&self.local, &self.state_dir)?;
+
+        // Items on the drive that could not be read are left alone on both
+        // sides, along with everything beneath them, rather than taken for
+        // deleted.
+        let unreadable: Vec<PathBuf> = remote
+            .iter()
+            .filter(|(_, node)| node.read_error().is_some())
+            .flat_map(|(path, node)| {
+                let synced = state.entries.get(node.id()).map(|entry| entry.path.clone());
+                std::iter::once(path.clone()).chain(synced)
+            })
+            .collect();
+        let hidden = |path: &Path| unreadable.iter().any(|unreadable| path.starts_with(unreadable));
+        remote.retain(|(path, node)| !matches!(node, DriveNode::Unknown(_)) && !hidden(path));
+        local.retain(|path, _| !hidden(path));
+        let held: Vec<SyncEntry> = state
+            .entries
+            .values()
+            .filter(|entry| hidden(&entry.path))
+            .cloned()
+            .collect();
+        state.entries.retain(|_, entry| !hidden(&entry.path));
+
+        let mut plan = plan::compare(state, &self.remote, &remote, &local, Utc::now());
+        plan.state
+            .entries
+            .extend(held.into_iter().map(|entry| (entry.id.clone(), entry)));
+        Ok(plan)
+    }
+
+    // Carries out a plan. An action that fails is reported and the rest go
+    // ahead; the items involved are looked at again by the next sync.
+    pub async fn apply(&self, drive: &mut DriveService, plan: SyncPlan) -> Result<SyncReport, Error> {
+        let partial = self.state_dir.join(PARTIAL_DIR);
+        if partial.exists() {
+            std::fs::remove_dir_all(&partial)?;
+        }
+        std::fs::create_dir_all(&partial)?;
+
+        let mut progress = Progress {
+            state: plan.state,
+            folders: plan.folders,
+            local_moves: Vec::new(),
+        };
+        progress.state.root = Some(self.remote.id.clone());
+        let mut report = SyncReport::default();
+        for (index, step) in plan.steps.into_iter().enumerate() {
+            match self.apply_step(drive, &step, &mut progress, &partial).await {
+                Ok(()) => report.applied.push(step.action),
+                Err(err) => report.failed.push((step.action, err)),
+            }
+            if (index + 1) % SAVE_INTERVAL == 0 {
+                progress.state.save(&self.state_path())?;
+            }
+        }
+
+        progress.state.synced = Some(Utc::now());
+        progress.state.save(&self.state_path())?;
+        std::fs::remove_dir_all(&partial)?;
+        Ok(report)
+    }
+
+    // Plans a sync and carries it out.
+    pub async fn run(&self, drive: &mut DriveService) -> Result<SyncReport, Error> {
+        let plan = self.plan(drive).await?;
+        self.apply(drive, plan).await
+    }
+
+    async fn apply_step(
+        &self,
+        drive: &mut DriveService,
+        step: &Step,
+        progress: &mut Progress,
+        partial: &Path,
+    ) -> Result<(), Error> {
+        let entry = match &step.action {
+            SyncAction::Download { path } => Some(self.download(drive, remote_file(step)?, path, partial).await?),
+            SyncAction::Upload { path } => {
+                let source = progress.local_path(&local(step)?.path);
+                Some(self.upload(drive, progress, &source, path).await?)
+            }
+            SyncAction::CreateLocalFolder { path } => {
+                std::fs::create_dir_all(self.local.join(path))?;
+                let item = local_item(&self.local, path)?;
+                Some(synced_entry(remote_node(step)?, path, &item))
+            }
+            SyncAction::CreateRemoteFolder { path } => {
+                let parent = progress.folder(path.parent())?;
+                let node = drive
+                    .create_folders(&parent, &[&file_name(path)?])
+                    .await?
+                    .pop()
+                    .ok_or_else(|| Error::InvalidResponse(String::from("Missing created folder")))?;
+                let folder = node.as_folder().cloned().ok_or(Error::InvalidDriveNodeType)?;
+                progress.folders.insert(path.clone(), folder);
+                Some(synced_entry(&node, path, local(step)?))
+            }
+            SyncAction::MoveLocal { from, to } => {
+                let from = progress.local_path(from);
+                move_file(&self.local.join(&from), &self.local.join(to))?;
+                progress.local_moves.push((from, to.clone()));
+                Some(synced_entry(remote_node(step)?, to, local(step)?))
+            }
+            SyncAction::MoveRemote { to, .. } => {
+                let parent = progress.folder(to.parent())?;
+                let mut node = remote_node(step)?.clone();
+                if node.parent_id() != Some(&parent.id) {
+                    node = drive.move_items(&[&node], &parent).await?.remove(0)?;
+                }
+                let name = file_name(to)?;
+                if node.full_name() != name {
+                    node = drive.rename(&node, &name).await?;
+                }
+                Some(synced_entry(&node, to, local(step)?))
+            }
+            SyncAction::DeleteLocal { path } => {
+                let path = self.local.join(progress.local_path(path));
+                if path.is_dir() {
+                    std::fs::remove_dir(path)?;
+                } else {
+                    std::fs::remove_file(path)?;
+                }
+                None
+            }
+            SyncAction::DeleteRemote { .. } => {
+                drive.delete(remote_node(step)?).await?;
+                None
+            }
+            SyncAction::Conflict { path, copy } => {
+                let source = progress.local_path(&local(step)?.path);
+                move_file(&self.local.join(source), &self.local.join(copy))?;
+                let copied = self.upload(drive, progress, copy, copy).await?;
+                progress.state.entries.insert(copied.id.clone(), copied);
+                Some(self.download(drive, remote_file(step)?, path, partial).await?)
+            }
+        };
+
+        if let Some(id) = &step.id {
+            progress.state.entries.remove(id);
+        }
+        if let Some(entry) = entry {
+            progress.state.entries.insert(entry.id.clone(), entry);
+        }
+        Ok(())
+    }
+
+    async fn download(
+        &self,
+        drive: &mut DriveService,
+        file: &File,
+        path: &Path,
+        partial: &Path,
+    ) -> Result<SyncEntry, Error> {
+        download_file(drive, file, partial, &self.local.join(path)).await?;
+        let item = local_item(&self.local, path)?;
+        Ok(synced_entry(&DriveNode::File(file.clone()), path, &item))
+    }
+
+    // Uploads the local file at `source` to `path` on the drive.
+    async fn upload(
+        &self,
+        drive: &mut DriveService,
+        progress: &Progress,
+        source: &Path,
+        path: &Path,
+    ) -> Result<SyncEntry, Error> {
+        let parent = progress.folder(path.parent())?;
+        // Taken before the upload, so a change made meanwhile is noticed by
+        // the next sync.
+        let item = local_item(&self.local, source)?;
+        let contents = std::fs::File::open(self.local.join(source))?;
+        let file = drive
+            .upload(&parent, &file_name(path)?, AllowStdIo::new(contents), item.meta.size)
+            .await?;
+        Ok(synced_entry(&DriveNode::File(file), path, &item))
+    }
+}
@@ -0,0 +1,506 @@
+use super::state::{LocalMeta, SyncEntry, SyncState};
+use crate::drive::{DriveNode, Folder};
+use crate::error::Error;
+use chrono::{DateTime, FixedOffset, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// An item found in the local directory.
+#[derive(Clone, Debug)]
+pub(super) struct LocalItem {
+    // Relative to the local directory.
+    pub(super) path: PathBuf,
+    pub(super) folder: bool,
+    pub(super) meta: LocalMeta,
+    pub(super) inode: Option<u64>,
+}
+
+#[cfg(unix)]
+fn inode(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_: &std::fs::Metadata) -> Option<u64> {
+    None
+}
+
+pub(super) fn local_item(root: &Path, path: &Path) -> Result<LocalItem, Error> {
+    let metadata = std::fs::symlink_metadata(root.join(path))?;
+    Ok(LocalItem {
+        path: path.to_path_buf(),
+        folder: metadata.is_dir(),
+        meta: LocalMeta {
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified()?,
+        },
+        inode: inode(&metadata),
+    })
+}
+
+// Lists everything beneath `root` except `skip`, without following
+// symlinks.
+pub(super) fn scan_local(root: &Path, skip: &Path) -> Result<BTreeMap<PathBuf, LocalItem>, Error> {
+    let mut items = BTreeMap::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if root.join(&path) == skip || entry.file_type()?.is_symlink() {
+                continue;
+            }
+            let item = local_item(root, &path)?;
+            if item.folder {
+                pending.push(path.clone());
+            }
+            items.insert(path, item);
+        }
+    }
+    Ok(items)
+}
+
+pub(super) fn is_folder(node: &DriveNode) -> bool {
+    node.as_folder().is_some()
+}
+
+// The size and modification date of a file on the drive.
+pub(super) fn remote_meta(node: &DriveNode) -> Option<(u64, DateTime<FixedOffset>)> {
+    match node {
+        DriveNode::File(file) => Some((file.size, file.date_modified)),
+        _ => None,
+    }
+}
+
+// The entry recording that `node` and `local` agree at `path`.
+pub(super) fn synced_entry(node: &DriveNode, path: &Path, local: &LocalItem) -> SyncEntry {
+    SyncEntry {
+        id: node.id().clone(),
+        path: path.to_path_buf(),
+        folder: is_folder(node),
+        etag: node.etag().cloned(),
+        remote: remote_meta(node),
+        local: (!local.folder).then_some(local.meta),
+        inode: local.inode,
+    }
+}
+
+// Whether a local file is likely a copy of one on the drive: the same size,
+// and the same modification date to the second.
+fn same_contents(local: &LocalItem, node: &DriveNode) -> bool {
+    let seconds = local
+        .meta
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .ok();
+    remote_meta(node).is_some_and(|(size, modified)| {
+        size == local.meta.size && seconds == Some(modified.timestamp())
+    })
+}
+
+// Where `path` ends up once the folders in `moves` are moved, going by the
+// deepest folder containing it.
+pub(super) fn rebase(path: &Path, moves: &[(PathBuf, PathBuf)]) -> PathBuf {
+    moves
+        .iter()
+        .filter(|(from, _)| path != from && path.starts_with(from))
+        .max_by_key(|(from, _)| from.components().count())
+        .and_then(|(from, to)| path.strip_prefix(from).ok().map(|rest| to.join(rest)))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// A name for the local side of a conflict, next to the item, e.g.
+// `report (conflicted copy 2024-05-01).txt`.
+fn conflicted_copy<F: Fn(&Path) -> bool>(path: &Path, now: DateTime<Utc>, taken: F) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let date = now.format("%Y-%m-%d");
+    (1..)
+        .map(|attempt| {
+            let name = match attempt {
+                1 => format!("{} (conflicted copy {}){}", stem, date, extension),
+                _ => format!("{} (conflicted copy {} {}){}", stem, date, attempt, extension),
+            };
+            path.with_file_name(name)
+        })
+        .find(|candidate| !taken(candidate))
+        .unwrap_or_default()
+}
+
+// A change a sync makes to bring both sides together. Paths are relative
+// to the local directory and the drive folder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncAction {
+    Download { path: PathBuf },
+    Upload { path: PathBuf },
+    CreateLocalFolder { path: PathBuf },
+    CreateRemoteFolder { path: PathBuf },
+    MoveLocal { from: PathBuf, to: PathBuf },
+    MoveRemote { from: PathBuf, to: PathBuf },
+    DeleteLocal { path: PathBuf },
+    // Moves the item on the drive to Recently Deleted.
+    DeleteRemote { path: PathBuf },
+    // Both sides changed a file. The local file is renamed to `copy` and
+    // uploaded, and the drive's version is downloaded in its place.
+    Conflict { path: PathBuf, copy: PathBuf },
+}
+
+impl SyncAction {
+    // Where the action leaves an item, for those that leave one.
+    fn target(&self) -> Option<&Path> {
+        match self {
+            SyncAction::Download { path }
+            | SyncAction::Upload { path }
+            | SyncAction::CreateLocalFolder { path }
+            | SyncAction::CreateRemoteFolder { path }
+            | SyncAction::Conflict { path, .. } => Some(path),
+            SyncAction::MoveLocal { to, .. } | SyncAction::MoveRemote { to, .. } => Some(to),
+            SyncAction::DeleteLocal { .. } | SyncAction::DeleteRemote { .. } => None,
+        }
+    }
+
+    // Folders are put in place from the top down, then files are
+    // transferred, then items are deleted from the bottom up.
+    fn order(&self) -> (u8, isize) {
+        let depth = |path: &Path| path.components().count() as isize;
+        match self {
+            SyncAction::CreateLocalFolder { path } | SyncAction::CreateRemoteFolder { path } => (0, depth(path)),
+            SyncAction::MoveLocal { to, .. } | SyncAction::MoveRemote { to, .. } => (0, depth(to)),
+            SyncAction::Download { .. } | SyncAction::Upload { .. } | SyncAction::Conflict { .. } => (1, 0),
+            SyncAction::DeleteLocal { path } | SyncAction::DeleteRemote { path } => (2, -depth(path)),
+        }
+    }
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::Download { path } => write!(f, "download {}", path.display()),
+            SyncAction::Upload { path } => write!(f, "upload {}", path.display()),
+            SyncAction::CreateLocalFolder { path } => write!(f, "create local folder {}", path.display()),
+            SyncAction::CreateRemoteFolder { path } => write!(f, "create remote folder {}", path.display()),
+            SyncAction::MoveLocal { from, to } => {
+                write!(f, "move local {} to {}", from.display(), to.display())
+            }
+            SyncAction::MoveRemote { from, to } => {
+                write!(f, "move remote {} to {}", from.display(), to.display())
+            }
+            SyncAction::DeleteLocal { path } => write!(f, "delete local {}", path.display()),
+            SyncAction::DeleteRemote { path } => write!(f, "delete remote {}", path.display()),
+            SyncAction::Conflict { path, copy } => {
+                write!(f, "conflict on {}, keeping local copy as {}", path.display(), copy.display())
+            }
+        }
+    }
+}
+
+pub(super) struct Step {
+    pub(super) action: SyncAction,
+    // The state entry the step supersedes.
+    pub(super) id: Option<String>,
+    pub(super) remote: Option<DriveNode>,
+    pub(super) local: Option<LocalItem>,
+}
+
+impl Step {
+    fn new(action: SyncAction, id: Option<&String>, remote: Option<&DriveNode>, local: Option<&LocalItem>) -> Step {
+        Step {
+            action,
+            id: id.cloned(),
+            remote: remote.cloned(),
+            local: local.cloned(),
+        }
+    }
+}
+
+// What a sync would do, in the order it would do it. Printing a plan lists
+// one action per line, which serves as a dry run.
+pub struct SyncPlan {
+    pub(super) steps: Vec<Step>,
+    // The state to start from, with the entries of items already in sync
+    // brought up to date.
+    pub(super) state: SyncState,
+    // The folders on the drive by the path they will have.
+    pub(super) folders: BTreeMap<PathBuf, Folder>,
+}
+
+impl SyncPlan {
+    pub fn actions(&self) -> impl Iterator<Item = &SyncAction> {
+        self.steps.iter().map(|step| &step.action)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl fmt::Display for SyncPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for action in self.actions() {
+            writeln!(f, "{}", action)?;
+        }
+        Ok(())
+    }
+}
+
+// Compares both sides with the state of the last sync. An item changed on
+// one side is brought over to the other; an item changed on both is a
+// conflict, except that a move on the drive wins over a local one.
+pub(super) fn compare(
+    mut state: SyncState,
+    root: &Folder,
+    remote: &[(PathBuf, DriveNode)],
+    local: &BTreeMap<PathBuf, LocalItem>,
+    now: DateTime<Utc>,
+) -> SyncPlan {
+    let remote_by_id: BTreeMap<&str, (&PathBuf, &DriveNode)> = remote
+        .iter()
+        .map(|(path, node)| (node.id().as_str(), (path, node)))
+        .collect();
+    let remote_paths: BTreeSet<&PathBuf> = remote.iter().map(|(path, _)| path).collect();
+    let state_paths: BTreeSet<PathBuf> = state.entries.values().map(|entry| entry.path.clone()).collect();
+    let by_inode: BTreeMap<u64, &LocalItem> = local
+        .values()
+        .filter_map(|item| Some((item.inode?, item)))
+        .collect();
+
+    let mut steps = Vec::new();
+    let mut claimed_local = BTreeSet::new();
+    let mut claimed_remote = BTreeSet::new();
+    let mut copies = BTreeSet::new();
+    let conflict = |path: &Path, copies: &mut BTreeSet<PathBuf>| {
+        let copy = conflicted_copy(path, now, |candidate| {
+            local.contains_key(candidate)
+                || remote_paths.contains(&candidate.to_path_buf())
+                || copies.contains(candidate)
+        });
+        copies.insert(copy.clone());
+        SyncAction::Conflict {
+            path: path.to_path_buf(),
+            copy,
+        }
+    };
+
+    let entries: Vec<SyncEntry> = state.entries.values().cloned().collect();
+    for entry in &entries {
+        let remote_item = remote_by_id
+            .get(entry.id.as_str())
+            .filter(|(_, node)| is_folder(node) == entry.folder)
+            .copied();
+        // A local item is followed by its inode once its path is gone.
+        let local_item = local
+            .get(&entry.path)
+            .filter(|item| item.folder == entry.folder)
+            .or_else(|| {
+                let item = by_inode.get(&entry.inode?)?;
+                let moved = item.folder == entry.folder
+                    && !state_paths.contains(&item.path)
+                    && !claimed_local.contains(&item.path);
+                moved.then_some(*item)
+            });
+        if remote_item.is_some() {
+            claimed_remote.insert(entry.id.clone());
+        }
+        if let Some(item) = local_item {
+            claimed_local.insert(item.path.clone());
+        }
+        let id = Some(&entry.id);
+
+        match (remote_item, local_item) {
+            (None, None) => {
+                state.entries.remove(&entry.id);
+            }
+            // Deleted locally, unless the drive changed it since.
+            (Some((path, node)), None) => {
+                let action = if remote_meta(node) != entry.remote {
+                    SyncAction::Download { path: path.clone() }
+                } else {
+                    SyncAction::DeleteRemote { path: path.clone() }
+                };
+                steps.push(Step::new(action, id, Some(node), None));
+            }
+            // Deleted on the drive, unless it was changed locally since.
+            (None, Some(item)) => {
+                let action = if !item.folder && Some(item.meta) != entry.local {
+                    SyncAction::Upload { path: item.path.clone() }
+                } else {
+                    SyncAction::DeleteLocal { path: item.path.clone() }
+                };
+                steps.push(Step::new(action, id, None, Some(item)));
+            }
+            (Some((path, node)), Some(item)) => {
+                let remote_changed = remote_meta(node) != entry.remote;
+                let local_changed = !item.folder && Some(item.meta) != entry.local;
+                let mut moved = true;
+                let target = if *path != entry.path {
+                    if item.path != *path {
+                        let action = SyncAction::MoveLocal {
+                            from: item.path.clone(),
+                            to: path.clone(),
+                        };
+                        steps.push(Step::new(action, id, Some(node), Some(item)));
+                    }
+                    path.clone()
+                } else if item.path != entry.path {
+                    let action = SyncAction::MoveRemote {
+                        from: path.clone(),
+                        to: item.path.clone(),
+                    };
+                    steps.push(Step::new(action, id, Some(node), Some(item)));
+                    item.path.clone()
+                } else {
+                    moved = false;
+                    entry.path.clone()
+                };
+                let item = LocalItem {
+                    path: target.clone(),
+                    ..item.clone()
+                };
+                let action = match (remote_changed, local_changed) {
+                    (true, true) => conflict(&target, &mut copies),
+                    (true, false) => SyncAction::Download { path: target },
+                    (false, true) => SyncAction::Upload { path: target },
+                    (false, false) => {
+                        if !moved {
+                            state.entries.insert(entry.id.clone(), synced_entry(node, &target, &item));
+                        }
+                        continue;
+                    }
+                };
+                steps.push(Step::new(action, id, Some(node), Some(&item)));
+            }
+        }
+    }
+
+    // Items new on the drive.
+    for (path, node) in remote {
+        if claimed_remote.contains(node.id()) {
+            continue;
+        }
+        match local.get(path).filter(|item| !claimed_local.contains(&item.path)) {
+            Some(item) => {
+                claimed_local.insert(item.path.clone());
+                // A file on one side and a folder on the other are left
+                // alone.
+                if item.folder != is_folder(node) {
+                    continue;
+                }
+                if item.folder || same_contents(item, node) {
+                    state.entries.insert(node.id().clone(), synced_entry(node, path, item));
+                } else {
+                    let action = conflict(path, &mut copies);
+                    steps.push(Step::new(action, None, Some(node), Some(item)));
+                }
+            }
+            None if is_folder(node) => {
+                let action = SyncAction::CreateLocalFolder { path: path.clone() };
+                steps.push(Step::new(action, None, Some(node), None));
+            }
+            None => {
+                let action = SyncAction::Download { path: path.clone() };
+                steps.push(Step::new(action, None, Some(node), None));
+            }
+        }
+    }
+
+    // Items new locally.
+    for item in local.values() {
+        if claimed_local.contains(&item.path) {
+            continue;
+        }
+        let action = if item.folder {
+            SyncAction::CreateRemoteFolder { path: item.path.clone() }
+        } else {
+            SyncAction::Upload { path: item.path.clone() }
+        };
+        steps.push(Step::new(action, None, None, Some(item)));
+    }
+
+    // A deleted folder that still has to hold something is brought back.
+    let targets: Vec<PathBuf> = steps
+        .iter()
+        .filter_map(|step| step.action.target().map(Path::to_path_buf))
+        .collect();
+    for step in &mut steps {
+        let needed = |path: &Path| targets.iter().any(|target| target != path && target.starts_with(path));
+        match &step.action {
+            SyncAction::DeleteLocal { path } if needed(path) => {
+                step.action = SyncAction::CreateRemoteFolder { path: path.clone() };
+            }
+            SyncAction::DeleteRemote { path } if needed(path) => {
+                step.action = SyncAction::CreateLocalFolder { path: path.clone() };
+            }
+            _ => {}
+        }
+    }
+
+    // Items beneath a folder that moves go with it, so targets are given
+    // where items end up and the moves this implies are dropped.
+    let local_moves: Vec<(PathBuf, PathBuf)> = steps
+        .iter()
+        .filter_map(|step| match &step.action {
+            SyncAction::MoveLocal { from, to } => Some((from.clone(), to.clone())),
+            _ => None,
+        })
+        .collect();
+    let remote_moves: Vec<(PathBuf, PathBuf)> = steps
+        .iter()
+        .filter_map(|step| match &step.action {
+            SyncAction::MoveRemote { from, to } => Some((from.clone(), to.clone())),
+            _ => None,
+        })
+        .collect();
+    let from_local = |path: &Path| rebase(path, &local_moves);
+    let from_remote = |path: &Path| rebase(path, &remote_moves);
+    steps.retain_mut(|step| {
+        let implied = match &mut step.action {
+            SyncAction::Download { path } | SyncAction::CreateLocalFolder { path } => {
+                *path = from_remote(path);
+                false
+            }
+            SyncAction::Upload { path } | SyncAction::CreateRemoteFolder { path } => {
+                *path = from_local(path);
+                false
+            }
+            SyncAction::Conflict { path, copy } => {
+                *path = from_remote(path);
+                *copy = from_remote(copy);
+                false
+            }
+            SyncAction::MoveLocal { from, to } => {
+                *to = from_remote(to);
+                from_local(from) == *to
+            }
+            SyncAction::MoveRemote { from, to } => {
+                *to = from_local(to);
+                from_remote(from) == *to
+            }
+            SyncAction::DeleteLocal { .. } | SyncAction::DeleteRemote { .. } => false,
+        };
+        if let (true, Some(node), Some(item), Some(to)) = (implied, &step.remote, &step.local, step.action.target()) {
+            state.entries.insert(node.id().clone(), synced_entry(node, to, item));
+        }
+        !implied
+    });
+    steps.sort_by_key(|step| step.action.order());
+
+    let mut folders = BTreeMap::new();
+    folders.insert(PathBuf::new(), root.clone());
+    for (path, node) in remote {
+        if let Some(folder) = node.as_folder() {
+            folders.insert(from_remote(path), folder.clone());
+        }
+    }
+
+    SyncPlan { steps, state, folders }
+}
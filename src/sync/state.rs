@@ -0,0 +1,49 @@
+use crate::error::Error;
+use crate::store::{load_json, save_json};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// The size and mtime of a local file, which change with its contents.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct LocalMeta {
+    pub(super) size: u64,
+    pub(super) modified: SystemTime,
+}
+
+// An item as it was the last time both sides agreed on it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(super) struct SyncEntry {
+    // The drivewsid of the item on the drive.
+    pub(super) id: String,
+    // Relative to both the local directory and the drive folder.
+    pub(super) path: PathBuf,
+    pub(super) folder: bool,
+    pub(super) etag: Option<String>,
+    // The size and modification date of a file on the drive.
+    pub(super) remote: Option<(u64, DateTime<FixedOffset>)>,
+    pub(super) local: Option<LocalMeta>,
+    // Follows a local item through renames, where the platform has inodes.
+    pub(super) inode: Option<u64>,
+}
+
+// The state database of a sync, keyed by drivewsid.
+#[derive(Serialize, Deserialize, Default)]
+pub(super) struct SyncState {
+    // The drivewsid of the folder the state belongs to.
+    pub(super) root: Option<String>,
+    pub(super) entries: BTreeMap<String, SyncEntry>,
+    pub(super) synced: Option<DateTime<Utc>>,
+}
+
+impl SyncState {
+    pub(super) fn load(path: &Path) -> Result<SyncState, Error> {
+        load_json(path)
+    }
+
+    pub(super) fn save(&self, path: &Path) -> Result<(), Error> {
+        save_json(path, self)
+    }
+}
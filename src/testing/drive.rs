@@ -0,0 +1,466 @@
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+pub static ROOT_ID: &str = "FOLDER::com.apple.CloudDocs::root";
+pub static TRASH_ID: &str = "TRASH_ROOT";
+pub static ZONE: &str = "com.apple.CloudDocs";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockNodeKind {
+    Folder,
+    File,
+    AppLibrary,
+}
+
+impl MockNodeKind {
+    // Whether nodes of this kind hold other nodes.
+    pub fn is_folder(self) -> bool {
+        self != MockNodeKind::File
+    }
+}
+
+// A file or folder stored by the mock drive.
+#[derive(Clone, Debug)]
+pub struct MockNode {
+    pub drivewsid: String,
+    pub docwsid: String,
+    pub zone: String,
+    pub parent_id: Option<String>,
+    pub name: String,
+    pub extension: Option<String>,
+    pub kind: MockNodeKind,
+    pub etag: String,
+    pub date_created: DateTime<Utc>,
+    pub date_modified: DateTime<Utc>,
+    pub contents: Vec<u8>,
+    // Whether the file is a package (bundle). Its `contents` stand in for
+    // the zip archive and are served through a `package_token`.
+    pub package: bool,
+    // The folder a trashed node was deleted from.
+    pub trashed_from: Option<String>,
+    pub date_deleted: Option<DateTime<Utc>>,
+    // Fields rendered in place of the usual ones, to stand in for items
+    // the client can't read.
+    pub overrides: Map<String, Value>,
+}
+
+// An in-memory iCloud Drive tree.
+pub struct MockDrive {
+    nodes: BTreeMap<String, MockNode>,
+    counter: u64,
+}
+
+// Splits a file name into the `name` and `extension` fields iCloud uses.
+// `MockNode::full_name` joins them again.
+pub fn split_name(name: &str) -> (String, Option<String>) {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            (String::from(stem), Some(String::from(extension)))
+        }
+        _ => (String::from(name), None),
+    }
+}
+
+impl MockNode {
+    // The node's name with its extension, as shown in paths.
+    pub fn full_name(&self) -> String {
+        match &self.extension {
+            Some(extension) => format!("{}.{}", self.name, extension),
+            None => self.name.clone(),
+        }
+    }
+}
+
+impl MockDrive {
+    pub fn new() -> MockDrive {
+        let now = Utc::now();
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            String::from(ROOT_ID),
+            MockNode {
+                drivewsid: String::from(ROOT_ID),
+                docwsid: String::from("root"),
+                zone: String::from(ZONE),
+                parent_id: None,
+                name: String::new(),
+                extension: None,
+                kind: MockNodeKind::Folder,
+                etag: String::from("1"),
+                date_created: now,
+                date_modified: now,
+                contents: Vec::new(),
+                package: false,
+                trashed_from: None,
+                date_deleted: None,
+                overrides: Map::new(),
+            },
+        );
+        nodes.insert(
+            String::from(TRASH_ID),
+            MockNode {
+                drivewsid: String::from(TRASH_ID),
+                docwsid: String::from("trash"),
+                zone: String::from(ZONE),
+                parent_id: None,
+                name: String::from("Recently Deleted"),
+                extension: None,
+                kind: MockNodeKind::Folder,
+                etag: String::from("1"),
+                date_created: now,
+                date_modified: now,
+                contents: Vec::new(),
+                package: false,
+                trashed_from: None,
+                date_deleted: None,
+                overrides: Map::new(),
+            },
+        );
+        MockDrive { nodes, counter: 1 }
+    }
+
+    // Adds the container of an app, with its own zone, to the root and
+    // returns its drivewsid.
+    pub fn add_app_library(&mut self, zone: &str, name: &str) -> String {
+        let now = Utc::now();
+        let drivewsid = format!("FOLDER::{}::documents", zone);
+        let etag = self.next_id();
+        self.nodes.insert(
+            drivewsid.clone(),
+            MockNode {
+                drivewsid: drivewsid.clone(),
+                docwsid: String::from("documents"),
+                zone: String::from(zone),
+                parent_id: Some(String::from(ROOT_ID)),
+                name: String::from(name),
+                extension: None,
+                kind: MockNodeKind::AppLibrary,
+                etag,
+                date_created: now,
+                date_modified: now,
+                contents: Vec::new(),
+                package: false,
+                trashed_from: None,
+                date_deleted: None,
+                overrides: Map::new(),
+            },
+        );
+        self.touch(ROOT_ID);
+        drivewsid
+    }
+
+    pub fn app_libraries(&self) -> Vec<&MockNode> {
+        self.nodes
+            .values()
+            .filter(|node| node.kind == MockNodeKind::AppLibrary)
+            .collect()
+    }
+
+    // Returns a new etag or document id.
+    pub fn next_id(&mut self) -> String {
+        self.counter += 1;
+        format!("{:x}", self.counter)
+    }
+
+    // Adds a folder and returns its drivewsid.
+    pub fn add_folder(&mut self, parent_id: &str, name: &str) -> String {
+        self.insert(parent_id, name, MockNodeKind::Folder, Vec::new())
+    }
+
+    // Adds a file and returns its drivewsid.
+    pub fn add_file(&mut self, parent_id: &str, name: &str, contents: &[u8]) -> String {
+        self.insert(parent_id, name, MockNodeKind::File, contents.to_vec())
+    }
+
+    fn insert(&mut self, parent_id: &str, name: &str, kind: MockNodeKind, contents: Vec<u8>) -> String {
+        let docwsid = format!("{}-{}", self.next_id(), name.len());
+        self.insert_with_docwsid(parent_id, name, kind, contents, docwsid)
+    }
+
+    // Adds a node with a document id chosen by the caller, as uploads do.
+    pub fn insert_with_docwsid(
+        &mut self,
+        parent_id: &str,
+        name: &str,
+        kind: MockNodeKind,
+        contents: Vec<u8>,
+        docwsid: String,
+    ) -> String {
+        let now = Utc::now();
+        let zone = self
+            .nodes
+            .get(parent_id)
+            .map_or_else(|| String::from(ZONE), |parent| parent.zone.clone());
+        let (name, extension) = match kind {
+            MockNodeKind::File => split_name(name),
+            _ => (String::from(name), None),
+        };
+        let prefix = match kind {
+            MockNodeKind::File => "FILE",
+            _ => "FOLDER",
+        };
+        let drivewsid = format!("{}::{}::{}", prefix, zone, docwsid);
+        let etag = self.next_id();
+        self.nodes.insert(
+            drivewsid.clone(),
+            MockNode {
+                drivewsid: drivewsid.clone(),
+                docwsid,
+                zone,
+                parent_id: Some(String::from(parent_id)),
+                name,
+                extension,
+                kind,
+                etag,
+                date_created: now,
+                date_modified: now,
+                contents,
+                package: false,
+                trashed_from: None,
+                date_deleted: None,
+                overrides: Map::new(),
+            },
+        );
+        self.touch(parent_id);
+        drivewsid
+    }
+
+    // Gives a node a new etag, as the server does after any change.
+    pub fn touch(&mut self, id: &str) {
+        let etag = self.next_id();
+        if let Some(node) = self.nodes.get_mut(id) {
+            node.etag = etag;
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MockNode> {
+        self.nodes.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut MockNode> {
+        self.nodes.get_mut(id)
+    }
+
+    // The combined size of every file.
+    pub fn total_size(&self) -> u64 {
+        self.nodes
+            .values()
+            .map(|node| node.contents.len() as u64)
+            .sum()
+    }
+
+    pub fn find_by_docwsid(&self, docwsid: &str) -> Option<&MockNode> {
+        self.nodes.values().find(|node| node.docwsid == docwsid)
+    }
+
+    pub fn children(&self, id: &str) -> Vec<&MockNode> {
+        self.nodes
+            .values()
+            .filter(|node| node.parent_id.as_deref() == Some(id))
+            .collect()
+    }
+
+    // Moves a node to the trash, remembering where it came from.
+    pub fn trash(&mut self, id: &str) -> bool {
+        let parent_id = match self.nodes.get_mut(id) {
+            Some(node) if node.parent_id.is_some() && node.trashed_from.is_none() => {
+                node.trashed_from = node.parent_id.replace(String::from(TRASH_ID));
+                node.date_deleted = Some(Utc::now());
+                node.trashed_from.clone()
+            }
+            _ => return false,
+        };
+        self.touch(id);
+        self.touch(TRASH_ID);
+        if let Some(parent_id) = parent_id {
+            self.touch(&parent_id);
+        }
+        true
+    }
+
+    // Puts a trashed node back in the folder it was deleted from, or in the
+    // root when that folder is gone or itself in the trash.
+    pub fn restore(&mut self, id: &str) -> bool {
+        let trashed_from = match self.nodes.get(id) {
+            Some(node) if node.parent_id.as_deref() == Some(TRASH_ID) => node.trashed_from.clone(),
+            _ => return false,
+        };
+        let parent_id = trashed_from
+            .filter(|parent_id| self.nodes.contains_key(parent_id) && !self.is_within(parent_id, TRASH_ID))
+            .unwrap_or_else(|| String::from(ROOT_ID));
+        if let Some(node) = self.nodes.get_mut(id) {
+            node.parent_id = Some(parent_id.clone());
+            node.trashed_from = None;
+            node.date_deleted = None;
+        }
+        self.touch(id);
+        self.touch(TRASH_ID);
+        self.touch(&parent_id);
+        true
+    }
+
+    // The path of a node relative to the root, e.g. `Documents/notes.txt`.
+    pub fn path_of(&self, id: &str) -> String {
+        let mut names = Vec::new();
+        let mut current = self.nodes.get(id);
+        while let Some(node) = current {
+            if node.drivewsid == ROOT_ID || node.drivewsid == TRASH_ID {
+                break;
+            }
+            names.push(node.full_name());
+            current = node.parent_id.as_deref().and_then(|parent_id| self.nodes.get(parent_id));
+        }
+        names.reverse();
+        names.join("/")
+    }
+
+    // Whether `id` is `ancestor` or lies beneath it.
+    pub fn is_within(&self, id: &str, ancestor: &str) -> bool {
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self.nodes.get(id).and_then(|node| node.parent_id.as_deref());
+        }
+        false
+    }
+
+    // Moves a node into another folder. A folder cannot be moved beneath
+    // itself.
+    pub fn move_node(&mut self, id: &str, destination_id: &str) -> bool {
+        match self.nodes.get(destination_id) {
+            Some(destination) if destination.kind.is_folder() => {}
+            _ => return false,
+        }
+        if self.is_within(destination_id, id) {
+            return false;
+        }
+        let parent_id = match self.nodes.get_mut(id) {
+            Some(node) if node.parent_id.is_some() => {
+                node.parent_id.replace(String::from(destination_id))
+            }
+            _ => return false,
+        };
+        self.touch(id);
+        self.touch(destination_id);
+        if let Some(parent_id) = parent_id {
+            self.touch(&parent_id);
+        }
+        true
+    }
+
+    // Copies a file into a folder and returns the copy's drivewsid.
+    pub fn copy_node(&mut self, id: &str, destination_id: &str) -> Option<String> {
+        match self.nodes.get(destination_id) {
+            Some(destination) if destination.kind.is_folder() => {}
+            _ => return None,
+        }
+        let node = self.nodes.get(id).filter(|node| node.kind == MockNodeKind::File)?.clone();
+        let copy_id = self.add_file(destination_id, &node.full_name(), &node.contents);
+        if let Some(copy) = self.nodes.get_mut(&copy_id) {
+            copy.package = node.package;
+        }
+        Some(copy_id)
+    }
+
+    // Removes a node and everything beneath it.
+    pub fn remove(&mut self, id: &str) -> Option<MockNode> {
+        let children: Vec<String> = self
+            .children(id)
+            .iter()
+            .map(|node| node.drivewsid.clone())
+            .collect();
+        for child in children {
+            self.remove(&child);
+        }
+        let node = self.nodes.remove(id)?;
+        if let Some(parent_id) = &node.parent_id {
+            self.touch(parent_id);
+        }
+        Some(node)
+    }
+
+    // Renders a node the way drivews describes items in replies.
+    pub fn item_json(&self, node: &MockNode) -> Value {
+        let mut value = json!({
+            "drivewsid": node.drivewsid,
+            "docwsid": node.docwsid,
+            "zone": node.zone,
+            "name": node.name,
+            "etag": node.etag,
+            "dateCreated": node.date_created.to_rfc3339(),
+        });
+        if let Some(parent_id) = &node.parent_id {
+            value["parentId"] = json!(parent_id);
+        }
+        if let (Some(trashed_from), Some(date_deleted)) = (&node.trashed_from, &node.date_deleted) {
+            let path = self.path_of(trashed_from);
+            value["restorePath"] = json!(if path.is_empty() {
+                self.path_of(&node.drivewsid)
+            } else {
+                format!("{}/{}", path, self.path_of(&node.drivewsid))
+            });
+            value["dateDeleted"] = json!(date_deleted.to_rfc3339());
+        }
+        match node.kind {
+            MockNodeKind::Folder | MockNodeKind::AppLibrary => {
+                let children = self.children(&node.drivewsid);
+                value["type"] = json!(if node.kind == MockNodeKind::AppLibrary {
+                    "APP_LIBRARY"
+                } else {
+                    "FOLDER"
+                });
+                value["directChildrenCount"] = json!(children.len());
+                value["numberOfItems"] = json!(children.len());
+                value["fileCount"] = json!(children
+                    .iter()
+                    .filter(|child| child.kind == MockNodeKind::File)
+                    .count());
+            }
+            MockNodeKind::File => {
+                value["type"] = json!("FILE");
+                value["size"] = json!(node.contents.len());
+                value["dateChanged"] = json!(node.date_modified.to_rfc3339());
+                value["dateModified"] = json!(node.date_modified.to_rfc3339());
+                value["lastOpenTime"] = json!(node.date_modified.to_rfc3339());
+                if let Some(extension) = &node.extension {
+                    value["extension"] = json!(extension);
+                }
+            }
+        }
+        for (key, field) in &node.overrides {
+            value[key] = field.clone();
+        }
+        value
+    }
+
+    // Renders a node the way `retrieveItemDetailsInFolders` does, with
+    // the metadata of its direct children for folders.
+    pub fn details_json(&self, id: &str) -> Value {
+        match self.nodes.get(id) {
+            Some(node) => {
+                let mut value = self.item_json(node);
+                if node.kind.is_folder() {
+                    value["items"] = Value::Array(
+                        self.children(id)
+                            .iter()
+                            .map(|child| self.item_json(child))
+                            .collect(),
+                    );
+                }
+                value
+            }
+            None => json!({
+                "drivewsid": id,
+                "status": "ID_INVALID",
+            }),
+        }
+    }
+}
+
+impl Default for MockDrive {
+    fn default() -> MockDrive {
+        MockDrive::new()
+    }
+}
@@ -0,0 +1,930 @@
+// A local stand-in for the iCloud web services, for tests that exercise
+// `Client` without a real Apple ID.
+use crate::account::Device;
+use crate::client::{Client, ClientBuilder};
+use crate::endpoint::Endpoints;
+use crate::error::Error;
+use crate::session::srp::SrpServer;
+use crate::session::TrustedPhoneNumber;
+use chrono::Utc;
+use hyper::body::Bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+mod drive;
+
+pub use drive::{split_name, MockDrive, MockNode, MockNodeKind, ROOT_ID, TRASH_ID, ZONE};
+
+static WEB_TOKEN_COOKIE: &str = "X-APPLE-WEBAUTH-TOKEN";
+static SRP_ITERATIONS: u32 = 1000;
+// The most items `retrieveItemDetailsInFolders` accepts in one request.
+const MAX_DETAILS_ITEMS: usize = 100;
+
+// The account served by a `MockServer`.
+#[derive(Clone, Debug)]
+pub struct MockAccount {
+    pub apple_id: String,
+    pub password: String,
+    pub full_name: String,
+    pub dsid: String,
+    pub security_code: String,
+    pub trusted_phone_numbers: Vec<TrustedPhoneNumber>,
+    pub devices: Vec<Device>,
+    pub quota_in_bytes: u64,
+}
+
+impl Default for MockAccount {
+    fn default() -> MockAccount {
+        MockAccount {
+            apple_id: String::from("user@example.com"),
+            password: String::from("password"),
+            full_name: String::from("Example User"),
+            dsid: String::from("1234567890"),
+            security_code: String::from("123456"),
+            trusted_phone_numbers: vec![TrustedPhoneNumber {
+                id: 1,
+                number_with_dial_code: Some(String::from("+1 (555) 555-0100")),
+                obfuscated_number: String::from("(•••) •••-••00"),
+                push_mode: Some(String::from("sms")),
+            }],
+            devices: vec![Device {
+                udid: Some(String::from("00000000-0000000000000000")),
+                name: Some(String::from("Example iPhone")),
+                model: Some(String::from("iPhone14,2")),
+                model_display_name: Some(String::from("iPhone 13 Pro")),
+                os_version: Some(String::from("iOS 17.0")),
+                serial_number: Some(String::from("XXXXXXXXXXXX")),
+            }],
+            quota_in_bytes: 5 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+// Failure modes the server can be switched into.
+#[derive(Clone, Debug, Default)]
+pub struct Failures {
+    // Require a second factor before the session is trusted.
+    pub require_2fa: bool,
+    // Answer `signin/init` with 404 so clients fall back to the plaintext
+    // sign-in.
+    pub disable_srp: bool,
+    // Answer the next requests with this status, regardless of path.
+    pub fail_next: Vec<StatusCode>,
+    // Web services reported with a non-active status by `accountLogin`.
+    pub unavailable_services: Vec<String>,
+    // Answer `copyItems` with 404 so clients copy by downloading and
+    // uploading.
+    pub disable_copy: bool,
+}
+
+struct SrpState {
+    server: SrpServer,
+    a: String,
+    c: String,
+}
+
+struct PendingUpload {
+    name: String,
+    contents: Option<Vec<u8>>,
+    // The etag of the document being replaced when the upload began.
+    etag: Option<String>,
+}
+
+struct State {
+    url: String,
+    uploads: std::collections::BTreeMap<String, PendingUpload>,
+    account: MockAccount,
+    drive: MockDrive,
+    failures: Failures,
+    srp: Option<SrpState>,
+    session_token: Option<String>,
+    verified: bool,
+    trust_tokens: Vec<String>,
+    web_token: Option<String>,
+    // The method and path of every request received.
+    requests: Vec<String>,
+}
+
+fn token() -> String {
+    let mut bytes = [0; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn respond(status: StatusCode, body: Value) -> Response<Body> {
+    let body = if body.is_null() {
+        Body::empty()
+    } else {
+        Body::from(body.to_string())
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert("Content-Type", "application/json".parse().unwrap());
+    response
+}
+
+fn cookie<'a>(request: &'a Request<Bytes>, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get_all(hyper::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn query_param(request: &Request<Bytes>, name: &str) -> Option<String> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| String::from(value))
+}
+
+impl State {
+    fn trusted(&self, trust_token: Option<&str>) -> bool {
+        !self.failures.require_2fa
+            || self.verified
+            || trust_token.is_some_and(|token| self.trust_tokens.iter().any(|t| t == token))
+    }
+
+    fn signed_in(&mut self, status: StatusCode) -> Response<Body> {
+        let session_token = token();
+        self.session_token = Some(session_token.clone());
+        self.verified = false;
+        let mut response = respond(status, json!({ "authType": "hsa2" }));
+        let headers = response.headers_mut();
+        headers.insert("X-Apple-Session-Token", session_token.parse().unwrap());
+        headers.insert("X-Apple-ID-Account-Country", "USA".parse().unwrap());
+        headers.insert("X-Apple-ID-Session-Id", token().parse().unwrap());
+        headers.insert("scnt", token().parse().unwrap());
+        response
+    }
+
+    fn sign_in_status(&self, trust_tokens: &Value) -> StatusCode {
+        let trusted = trust_tokens
+            .as_array()
+            .is_some_and(|tokens| {
+                tokens
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|token| self.trust_tokens.iter().any(|t| t == token))
+            });
+        if self.failures.require_2fa && !trusted {
+            StatusCode::CONFLICT
+        } else {
+            StatusCode::OK
+        }
+    }
+
+    fn web_authenticated(&self, request: &Request<Bytes>) -> bool {
+        match (&self.web_token, cookie(request, WEB_TOKEN_COOKIE)) {
+            (Some(expected), Some(actual)) => expected == actual,
+            _ => false,
+        }
+    }
+
+    fn webservices(&self) -> Value {
+        let mut services = serde_json::Map::new();
+        for name in ["drivews", "docws", "ckdatabasews", "account"] {
+            let status = if self.failures.unavailable_services.iter().any(|s| s == name) {
+                "unavailable"
+            } else {
+                "active"
+            };
+            services.insert(
+                String::from(name),
+                json!({ "url": format!("{}/{}", self.url, name), "status": status }),
+            );
+        }
+        Value::Object(services)
+    }
+
+    fn handle(&mut self, request: Request<Bytes>) -> Response<Body> {
+        self.requests
+            .push(format!("{} {}", request.method(), request.uri().path()));
+        if !self.failures.fail_next.is_empty() {
+            let status = self.failures.fail_next.remove(0);
+            return respond(status, json!({ "error": status.as_u16() }));
+        }
+
+        let body: Value = serde_json::from_slice(request.body()).unwrap_or(Value::Null);
+        let path = request.uri().path().to_string();
+        let method = request.method().clone();
+
+        match (method, path.as_str()) {
+            (Method::POST, "/appleauth/auth/signin/init") => self.signin_init(&body),
+            (Method::POST, "/appleauth/auth/signin/complete") => self.signin_complete(&body),
+            (Method::POST, "/appleauth/auth/signin") => self.signin(&body),
+            (Method::GET, "/appleauth/auth") => respond(
+                StatusCode::OK,
+                json!({ "trustedPhoneNumbers": self.account.trusted_phone_numbers }),
+            ),
+            (Method::POST, "/appleauth/auth/verify/trusteddevice/securitycode") => {
+                self.verify_code(&body, StatusCode::NO_CONTENT)
+            }
+            (Method::PUT, "/appleauth/auth/verify/phone") => {
+                let id = body["phoneNumber"]["id"].as_u64();
+                if self
+                    .account
+                    .trusted_phone_numbers
+                    .iter()
+                    .any(|phone| Some(u64::from(phone.id)) == id)
+                {
+                    respond(StatusCode::OK, json!({}))
+                } else {
+                    respond(StatusCode::BAD_REQUEST, json!({ "error": "Unknown phone number" }))
+                }
+            }
+            (Method::POST, "/appleauth/auth/verify/phone/securitycode") => {
+                self.verify_code(&body, StatusCode::OK)
+            }
+            (Method::GET, "/appleauth/auth/2sv/trust") => {
+                if self.verified {
+                    let trust_token = token();
+                    self.trust_tokens.push(trust_token.clone());
+                    let mut response = respond(StatusCode::NO_CONTENT, Value::Null);
+                    response
+                        .headers_mut()
+                        .insert("X-Apple-TwoSV-Trust-Token", trust_token.parse().unwrap());
+                    response
+                } else {
+                    respond(StatusCode::UNAUTHORIZED, json!({ "error": "Not verified" }))
+                }
+            }
+            (Method::POST, "/setup/ws/1/accountLogin") => self.account_login(&body),
+            (Method::POST, "/setup/ws/1/validate") => {
+                if self.web_authenticated(&request) {
+                    let trusted = self.trusted(None);
+                    respond(
+                        StatusCode::OK,
+                        json!({
+                            "hsaChallengeRequired": self.failures.require_2fa,
+                            "hsaTrustedBrowser": trusted,
+                        }),
+                    )
+                } else {
+                    respond(StatusCode::from_u16(421).unwrap(), json!({ "error": "Invalid session" }))
+                }
+            }
+            (Method::POST, "/setup/ws/1/logout") => {
+                if self.web_authenticated(&request) {
+                    self.web_token = None;
+                    self.session_token = None;
+                    self.verified = false;
+                    if body["trustBrowsers"] == false {
+                        self.trust_tokens.clear();
+                    }
+                    respond(StatusCode::OK, json!({ "success": true }))
+                } else {
+                    respond(StatusCode::UNAUTHORIZED, json!({ "error": "Unauthorized" }))
+                }
+            }
+            (Method::POST, "/setup/ws/1/storageUsageInfo") => {
+                if self.web_authenticated(&request) {
+                    self.storage_usage()
+                } else {
+                    respond(StatusCode::UNAUTHORIZED, json!({ "error": "Unauthorized" }))
+                }
+            }
+            (Method::GET, "/account/setup/web/device/getDevices") => {
+                if self.web_authenticated(&request) {
+                    respond(StatusCode::OK, json!({ "devices": self.account.devices }))
+                } else {
+                    respond(StatusCode::UNAUTHORIZED, json!({ "error": "Unauthorized" }))
+                }
+            }
+            (Method::GET, path) if path.starts_with("/content/") => {
+                match self.drive.find_by_docwsid(&path["/content/".len()..]) {
+                    Some(node) => {
+                        let mut response = Response::new(Body::from(node.contents.clone()));
+                        response.headers_mut().insert(
+                            hyper::header::CONTENT_LENGTH,
+                            node.contents.len().into(),
+                        );
+                        response
+                    }
+                    None => respond(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+                }
+            }
+            (Method::POST, path) if path.starts_with("/upload/") => {
+                self.receive_upload(&path["/upload/".len()..], &request)
+            }
+            (_, path) if path.starts_with("/drivews/") || path.starts_with("/docws/") => {
+                if self.web_authenticated(&request) {
+                    self.handle_drive(&request, &body)
+                } else {
+                    respond(StatusCode::UNAUTHORIZED, json!({ "error": "Unauthorized" }))
+                }
+            }
+            _ => respond(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+        }
+    }
+
+    fn signin_init(&mut self, body: &Value) -> Response<Body> {
+        if self.failures.disable_srp {
+            return respond(StatusCode::NOT_FOUND, json!({ "error": "Not found" }));
+        }
+        if body["accountName"].as_str() != Some(self.account.apple_id.as_str()) {
+            return respond(StatusCode::UNAUTHORIZED, json!({ "error": "Unknown account" }));
+        }
+        let a = match body["a"].as_str() {
+            Some(a) => String::from(a),
+            None => return respond(StatusCode::BAD_REQUEST, json!({ "error": "Missing a" })),
+        };
+
+        let mut salt = [0; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let server = match SrpServer::new(&self.account.password, &salt, SRP_ITERATIONS, "s2k") {
+            Ok(server) => server,
+            Err(err) => {
+                return respond(StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": err.to_string() }))
+            }
+        };
+        let c = token();
+        let response = json!({
+            "iteration": SRP_ITERATIONS,
+            "salt": base64::Engine::encode(&base64::engine::general_purpose::STANDARD, salt),
+            "protocol": "s2k",
+            "b": server.public_key(),
+            "c": c,
+        });
+        self.srp = Some(SrpState { server, a, c });
+        respond(StatusCode::OK, response)
+    }
+
+    fn signin_complete(&mut self, body: &Value) -> Response<Body> {
+        let srp = match self.srp.take() {
+            Some(srp) => srp,
+            None => return respond(StatusCode::BAD_REQUEST, json!({ "error": "No handshake" })),
+        };
+        let username = body["accountName"].as_str().unwrap_or_default();
+        let valid = body["c"].as_str() == Some(srp.c.as_str())
+            && username == self.account.apple_id
+            && srp
+                .server
+                .verify(username, &srp.a, body["m1"].as_str().unwrap_or_default())
+                .unwrap_or(false);
+        if valid {
+            let status = self.sign_in_status(&body["trustTokens"]);
+            self.signed_in(status)
+        } else {
+            respond(StatusCode::UNAUTHORIZED, json!({ "error": "Invalid credentials" }))
+        }
+    }
+
+    fn signin(&mut self, body: &Value) -> Response<Body> {
+        if body["accountName"].as_str() == Some(self.account.apple_id.as_str())
+            && body["password"].as_str() == Some(self.account.password.as_str())
+        {
+            let status = self.sign_in_status(&body["trustTokens"]);
+            let mut response = self.signed_in(StatusCode::OK);
+            response
+                .headers_mut()
+                .insert("X-Apple-I-Rscd", status.as_u16().into());
+            response
+        } else {
+            respond(StatusCode::UNAUTHORIZED, json!({ "error": "Invalid credentials" }))
+        }
+    }
+
+    fn verify_code(&mut self, body: &Value, status: StatusCode) -> Response<Body> {
+        if self.session_token.is_some()
+            && body["securityCode"]["code"].as_str() == Some(self.account.security_code.as_str())
+        {
+            self.verified = true;
+            respond(status, Value::Null)
+        } else {
+            respond(StatusCode::BAD_REQUEST, json!({ "error": "Incorrect verification code" }))
+        }
+    }
+
+    fn account_login(&mut self, body: &Value) -> Response<Body> {
+        let session_token = body["dsWebAuthToken"].as_str();
+        if session_token.is_none() || session_token != self.session_token.as_deref() {
+            return respond(StatusCode::from_u16(421).unwrap(), json!({ "error": "Invalid token" }));
+        }
+
+        let trusted = self.trusted(body["trustToken"].as_str());
+        let web_token = token();
+        self.web_token = Some(web_token.clone());
+
+        let mut response = respond(
+            StatusCode::OK,
+            json!({
+                "dsInfo": {
+                    "dsid": self.account.dsid,
+                    "appleId": self.account.apple_id,
+                    "appleIdAliases": [],
+                    "fullName": self.account.full_name,
+                    "primaryEmail": self.account.apple_id,
+                    "locale": "en_US",
+                    "languageCode": "en-us",
+                    "hsaVersion": 2,
+                },
+                "webservices": self.webservices(),
+                "hsaChallengeRequired": self.failures.require_2fa,
+                "hsaTrustedBrowser": trusted,
+            }),
+        );
+        response.headers_mut().insert(
+            hyper::header::SET_COOKIE,
+            format!("{}={}; Path=/; HttpOnly", WEB_TOKEN_COOKIE, web_token)
+                .parse()
+                .unwrap(),
+        );
+        response
+    }
+
+    fn storage_usage(&self) -> Response<Body> {
+        let used: u64 = self.drive.total_size();
+        respond(
+            StatusCode::OK,
+            json!({
+                "storageUsageByMedia": [
+                    {
+                        "mediaKey": "docs",
+                        "displayLabel": "Documents",
+                        "displayColor": "ffa500",
+                        "usageInBytes": used,
+                    }
+                ],
+                "storageUsageInfo": {
+                    "compStorageInBytes": 0,
+                    "usedStorageInBytes": used,
+                    "totalStorageInBytes": self.account.quota_in_bytes,
+                    "commerceStorageInBytes": 0,
+                },
+                "quotaStatus": {
+                    "overQuota": used > self.account.quota_in_bytes,
+                    "haveMaxQuotaTier": false,
+                    "almost-full": used * 10 > self.account.quota_in_bytes * 9,
+                    "paidQuota": false,
+                },
+            }),
+        )
+    }
+
+    fn handle_drive(&mut self, request: &Request<Bytes>, body: &Value) -> Response<Body> {
+        match (request.method(), request.uri().path()) {
+            (&Method::POST, "/drivews/retrieveItemDetailsInFolders") => {
+                let requested = body.as_array().cloned().unwrap_or_default();
+                if requested.len() > MAX_DETAILS_ITEMS {
+                    return respond(StatusCode::BAD_REQUEST, json!({ "error": "Too many items" }));
+                }
+                let items: Vec<Value> = requested
+                    .iter()
+                    .map(|item| {
+                        let id = item["drivewsid"].as_str().unwrap_or_default();
+                        // With partialData, folders are described without
+                        // their children.
+                        match self.drive.get(id) {
+                            Some(node) if item["partialData"].as_bool() == Some(true) => {
+                                self.drive.item_json(node)
+                            }
+                            _ => self.drive.details_json(id),
+                        }
+                    })
+                    .collect();
+                respond(StatusCode::OK, Value::Array(items))
+            }
+            (&Method::GET, "/drivews/retrieveAppLibraries") => {
+                let items: Vec<Value> = self
+                    .drive
+                    .app_libraries()
+                    .iter()
+                    .map(|library| self.drive.item_json(library))
+                    .collect();
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::POST, "/drivews/createFolders") => {
+                let parent_id = body["destinationDrivewsId"].as_str().unwrap_or_default();
+                match self.drive.get(parent_id) {
+                    Some(parent) if parent.kind.is_folder() => {}
+                    _ => return respond(StatusCode::NOT_FOUND, json!({ "error": "Unknown folder" })),
+                }
+                let mut folders = Vec::new();
+                for folder in body["folders"].as_array().cloned().unwrap_or_default() {
+                    let id = self
+                        .drive
+                        .add_folder(parent_id, folder["name"].as_str().unwrap_or_default());
+                    let mut item = self.drive.details_json(&id);
+                    item["clientId"] = folder["clientId"].clone();
+                    folders.push(item);
+                }
+                respond(
+                    StatusCode::OK,
+                    json!({ "destinationDrivewsId": parent_id, "folders": folders }),
+                )
+            }
+            (&Method::POST, "/drivews/renameItems") => {
+                let items = self.each_item(body, |drive, id, item| {
+                    let parent_id = drive.get(id).and_then(|node| node.parent_id.clone());
+                    if let Some(node) = drive.get_mut(id) {
+                        node.name = String::from(item["name"].as_str().unwrap_or_default());
+                        if node.kind == MockNodeKind::File {
+                            node.extension = item["extension"]
+                                .as_str()
+                                .filter(|extension| !extension.is_empty())
+                                .map(String::from);
+                        }
+                    }
+                    drive.touch(id);
+                    if let Some(parent_id) = parent_id {
+                        drive.touch(&parent_id);
+                    }
+                    true
+                });
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::POST, "/drivews/moveItemsToTrash") => {
+                let items = self.each_item(body, |drive, id, _| drive.trash(id));
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::POST, "/drivews/putBackItemsFromTrash") => {
+                let items = self.each_item(body, |drive, id, _| drive.restore(id));
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::POST, "/drivews/deleteItems") => {
+                let items = self.each_item(body, |drive, id, _| drive.remove(id).is_some());
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::POST, "/drivews/moveItems") => {
+                let destination_id = String::from(body["destinationDrivewsId"].as_str().unwrap_or_default());
+                let items = self.each_item(body, |drive, id, _| drive.move_node(id, &destination_id));
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::POST, "/drivews/copyItems") => {
+                if self.failures.disable_copy {
+                    return respond(StatusCode::NOT_FOUND, json!({ "error": "Not found" }));
+                }
+                let destination_id = String::from(body["destinationDrivewsId"].as_str().unwrap_or_default());
+                let mut copies = Vec::new();
+                let items = self.each_item(body, |drive, id, _| match drive.copy_node(id, &destination_id) {
+                    Some(copy_id) => {
+                        copies.push(copy_id);
+                        true
+                    }
+                    None => false,
+                });
+                // Successful entries describe the copy rather than the original.
+                let mut copies = copies.into_iter();
+                let items: Vec<Value> = items
+                    .into_iter()
+                    .map(|item| match item.get("status") {
+                        Some(_) => item,
+                        None => copies
+                            .next()
+                            .and_then(|id| self.drive.get(&id))
+                            .map(|node| self.drive.item_json(node))
+                            .unwrap_or(item),
+                    })
+                    .collect();
+                respond(StatusCode::OK, json!({ "items": items }))
+            }
+            (&Method::GET, path) if path.starts_with("/docws/ws/") && path.ends_with("/download/by_id") => {
+                let document_id = query_param(request, "document_id").unwrap_or_default();
+                match self.drive.find_by_docwsid(&document_id) {
+                    Some(node) if node.kind == MockNodeKind::File => {
+                        let token = json!({ "url": format!("{}/content/{}", self.url, node.docwsid) });
+                        if node.package {
+                            respond(StatusCode::OK, json!({ "document_id": document_id, "package_token": token }))
+                        } else {
+                            respond(StatusCode::OK, json!({ "document_id": document_id, "data_token": token }))
+                        }
+                    }
+                    _ => respond(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+                }
+            }
+            (&Method::POST, path) if path.starts_with("/docws/ws/") && path.ends_with("/upload/web") => {
+                let document_id = match body["document_id"].as_str() {
+                    Some(document_id) => String::from(document_id),
+                    None => format!("upload-{}", self.drive.next_id()),
+                };
+                let etag = self.drive.find_by_docwsid(&document_id).map(|node| node.etag.clone());
+                self.uploads.insert(
+                    document_id.clone(),
+                    PendingUpload {
+                        name: String::from(body["filename"].as_str().unwrap_or_default()),
+                        contents: None,
+                        etag,
+                    },
+                );
+                respond(
+                    StatusCode::OK,
+                    json!([{
+                        "document_id": document_id,
+                        "url": format!("{}/upload/{}", self.url, document_id),
+                    }]),
+                )
+            }
+            (&Method::POST, path) if path.starts_with("/docws/ws/") && path.ends_with("/update/documents") => {
+                self.commit_upload(body)
+            }
+            _ => respond(StatusCode::NOT_FOUND, json!({ "error": "Not found" })),
+        }
+    }
+
+    // Applies `f` to each `{drivewsid, etag}` entry of `body.items`, and
+    // renders the result of each the way drivews does. Entries with a stale
+    // etag are refused with `ETAG_CONFLICT`.
+    fn each_item<F>(&mut self, body: &Value, mut f: F) -> Vec<Value>
+    where
+        F: FnMut(&mut MockDrive, &str, &Value) -> bool,
+    {
+        let mut results = Vec::new();
+        for item in body["items"].as_array().cloned().unwrap_or_default() {
+            let id = item["drivewsid"].as_str().unwrap_or_default();
+            let status = match self.drive.get(id) {
+                None => Some("ID_INVALID"),
+                Some(node) => match item["etag"].as_str() {
+                    Some(etag) if !etag.is_empty() && etag != node.etag => Some("ETAG_CONFLICT"),
+                    _ => None,
+                },
+            };
+            let status = match status {
+                Some(status) => Some(status),
+                None if f(&mut self.drive, id, &item) => None,
+                None => Some("UNKNOWN_ERROR"),
+            };
+            results.push(match (status, self.drive.get(id)) {
+                (None, Some(node)) => self.drive.item_json(node),
+                (None, None) => json!({ "drivewsid": id, "status": "OK" }),
+                (status, _) => json!({
+                    "drivewsid": id,
+                    "status": status.unwrap_or("ID_INVALID"),
+                }),
+            });
+        }
+        results
+    }
+
+    // Stores the contents of a multipart upload and returns its receipt.
+    fn receive_upload(&mut self, document_id: &str, request: &Request<Bytes>) -> Response<Body> {
+        let boundary = request
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once("boundary="))
+            .map(|(_, boundary)| format!("\r\n--{}--", boundary));
+        let body = request.body();
+        let start = body.windows(4).position(|window| window == b"\r\n\r\n");
+        let contents = match (boundary, start) {
+            (Some(boundary), Some(start)) => {
+                let data = &body[start + 4..];
+                let end = data
+                    .windows(boundary.len())
+                    .rposition(|window| window == boundary.as_bytes());
+                end.map(|end| data[..end].to_vec())
+            }
+            _ => None,
+        };
+
+        match (self.uploads.get_mut(document_id), contents) {
+            (Some(upload), Some(contents)) => {
+                let size = contents.len();
+                upload.contents = Some(contents);
+                respond(
+                    StatusCode::OK,
+                    json!({
+                        "singleFile": {
+                            "fileChecksum": format!("checksum-{}", document_id),
+                            "wrappingKey": "wrapping-key",
+                            "referenceChecksum": format!("reference-{}", document_id),
+                            "receipt": format!("receipt-{}", document_id),
+                            "size": size,
+                        }
+                    }),
+                )
+            }
+            _ => respond(StatusCode::BAD_REQUEST, json!({ "error": "Invalid upload" })),
+        }
+    }
+
+    fn commit_upload(&mut self, body: &Value) -> Response<Body> {
+        let document_id = body["document_id"].as_str().unwrap_or_default();
+        let receipt = body["data"]["receipt"].as_str().unwrap_or_default();
+        if receipt != format!("receipt-{}", document_id) {
+            return respond(StatusCode::BAD_REQUEST, json!({ "error": "Invalid receipt" }));
+        }
+        let upload = match self.uploads.remove(document_id) {
+            Some(PendingUpload { name, contents: Some(contents), etag }) => (name, contents, etag),
+            _ => return respond(StatusCode::BAD_REQUEST, json!({ "error": "Unknown upload" })),
+        };
+        let parent_id = match self
+            .drive
+            .find_by_docwsid(body["path"]["starting_document_id"].as_str().unwrap_or_default())
+        {
+            Some(parent) if parent.kind.is_folder() => parent.drivewsid.clone(),
+            _ => return respond(StatusCode::NOT_FOUND, json!({ "error": "Unknown folder" })),
+        };
+
+        let existing = self
+            .drive
+            .find_by_docwsid(document_id)
+            .map(|node| node.drivewsid.clone());
+        match existing {
+            // The document changed while its new contents were uploaded.
+            Some(id) if self.drive.get(&id).map(|node| &node.etag) != upload.2.as_ref() => {
+                return respond(
+                    StatusCode::OK,
+                    json!({
+                        "results": [{
+                            "status": { "status_code": 1, "error_message": "ETAG_CONFLICT" },
+                            "document": { "document_id": document_id },
+                        }]
+                    }),
+                );
+            }
+            Some(id) => {
+                if let Some(node) = self.drive.get_mut(&id) {
+                    node.contents = upload.1;
+                    node.date_modified = Utc::now();
+                }
+                self.drive.touch(&id);
+                self.drive.touch(&parent_id);
+            }
+            None => {
+                self.drive.insert_with_docwsid(
+                    &parent_id,
+                    &upload.0,
+                    MockNodeKind::File,
+                    upload.1,
+                    String::from(document_id),
+                );
+            }
+        }
+        respond(
+            StatusCode::OK,
+            json!({
+                "results": [{
+                    "status": { "status_code": 0, "error_message": "" },
+                    "document": { "document_id": document_id },
+                }]
+            }),
+        )
+    }
+}
+
+// A local HTTP server emulating the iCloud endpoints used by this crate.
+//
+// The server stops when dropped.
+pub struct MockServer {
+    url: String,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    // Starts a server on an ephemeral localhost port.
+    pub async fn start(account: MockAccount) -> Result<MockServer, Error> {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let builder = Server::try_bind(&addr)?;
+        let state = Arc::new(Mutex::new(State {
+            url: String::new(),
+            uploads: std::collections::BTreeMap::new(),
+            account,
+            drive: MockDrive::new(),
+            failures: Failures::default(),
+            srp: None,
+            session_token: None,
+            verified: false,
+            trust_tokens: Vec::new(),
+            web_token: None,
+            requests: Vec::new(),
+        }));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let state = state.clone();
+                    async move {
+                        let (parts, body) = request.into_parts();
+                        let body = hyper::body::to_bytes(body).await?;
+                        let request = Request::from_parts(parts, body);
+                        let response = match state.lock() {
+                            Ok(mut state) => state.handle(request),
+                            Err(_) => respond(StatusCode::INTERNAL_SERVER_ERROR, Value::Null),
+                        };
+                        Ok::<_, hyper::Error>(response)
+                    }
+                }))
+            }
+        });
+
+        let server = builder.serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        state.lock().map_err(|_| Error::MutexError)?.url = url.clone();
+
+        let (shutdown, receiver) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            receiver.await.ok();
+        }));
+
+        Ok(MockServer {
+            url,
+            state,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    // The base URL of the server, e.g. `http://127.0.0.1:49152`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // The endpoint profile pointing at this server.
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints::custom(
+            &format!("{}/appleauth/auth", self.url),
+            &format!("{}/setup/ws/1", self.url),
+            &self.url,
+        )
+    }
+
+    // A client builder configured to talk to this server.
+    pub fn client_builder(&self) -> ClientBuilder {
+        Client::builder()
+            .endpoints(self.endpoints())
+            .https_only(false)
+    }
+
+    fn with_state<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut State) -> R,
+    {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        f(&mut state)
+    }
+
+    // Runs `f` with mutable access to the drive tree.
+    pub fn with_drive<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut MockDrive) -> R,
+    {
+        self.with_state(|state| f(&mut state.drive))
+    }
+
+    // Runs `f` with mutable access to the failure switches.
+    pub fn with_failures<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Failures) -> R,
+    {
+        self.with_state(|state| f(&mut state.failures))
+    }
+
+    // Requires a second factor for sessions that are not yet trusted.
+    pub fn require_2fa(&self, required: bool) {
+        self.with_failures(|failures| failures.require_2fa = required);
+    }
+
+    // Answers the next request with `status`.
+    pub fn fail_next(&self, status: StatusCode) {
+        self.with_failures(|failures| failures.fail_next.push(status));
+    }
+
+    // Invalidates the web session so drive requests get a 401 until the
+    // client calls `accountLogin` again.
+    pub fn expire_session(&self) {
+        self.with_state(|state| state.web_token = None);
+    }
+
+    // Invalidates the session token and trust tokens so the client has to
+    // sign in with credentials again.
+    pub fn revoke_tokens(&self) {
+        self.with_state(|state| {
+            state.web_token = None;
+            state.session_token = None;
+            state.trust_tokens.clear();
+            state.verified = false;
+        });
+    }
+
+    // The method and path of every request received so far, e.g.
+    // `POST /drivews/retrieveItemDetailsInFolders`.
+    pub fn requests(&self) -> Vec<String> {
+        self.with_state(|state| state.requests.clone())
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::Transport;
+use crate::error::Error;
+use futures::future::BoxFuture;
+use hyper::body::Bytes;
+use hyper::{Body, HeaderMap, Method, Request, Response, StatusCode, Uri};
+use std::sync::{Arc, Mutex};
+
+// A request received by a `MockTransport`.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+type Handler = Box<dyn Fn(&RecordedRequest) -> Response<Body> + Send + Sync>;
+
+struct Route {
+    method: Method,
+    path: String,
+    handler: Handler,
+}
+
+// An in-memory transport that answers requests from registered routes.
+//
+// Routes are matched on method and URI path; the most recently registered
+// match wins. Clones share the same routes and request log.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    routes: Arc<Mutex<Vec<Route>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    // Answers requests to `path` with a fixed status and JSON body.
+    pub fn on(&self, method: Method, path: &str, status: StatusCode, body: &str) -> &MockTransport {
+        let body = Bytes::copy_from_slice(body.as_bytes());
+        self.on_fn(method, path, move |_| {
+            let mut response = Response::new(Body::from(body.clone()));
+            *response.status_mut() = status;
+            response
+        })
+    }
+
+    // Answers requests to `path` with a response built by `handler`.
+    pub fn on_fn<F>(&self, method: Method, path: &str, handler: F) -> &MockTransport
+    where
+        F: Fn(&RecordedRequest) -> Response<Body> + Send + Sync + 'static,
+    {
+        if let Ok(mut routes) = self.routes.lock() {
+            routes.push(Route {
+                method,
+                path: String::from(path),
+                handler: Box::new(handler),
+            });
+        }
+        self
+    }
+
+    // The requests received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests
+            .lock()
+            .map(|requests| requests.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, request: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let recorded = RecordedRequest {
+                method: parts.method,
+                uri: parts.uri,
+                headers: parts.headers,
+                body: hyper::body::to_bytes(body).await?,
+            };
+
+            let response = {
+                let routes = self.routes.lock().map_err(|_| Error::MutexError)?;
+                routes
+                    .iter()
+                    .rev()
+                    .find(|route| route.method == recorded.method && route.path == recorded.uri.path())
+                    .map(|route| (route.handler)(&recorded))
+            };
+
+            self.requests
+                .lock()
+                .map_err(|_| Error::MutexError)?
+                .push(recorded.clone());
+
+            response.ok_or_else(|| {
+                Error::UnexpectedRequest(format!("{} {}", recorded.method, recorded.uri))
+            })
+        })
+    }
+}
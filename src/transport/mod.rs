@@ -0,0 +1,41 @@
+use crate::error::Error;
+use futures::future::BoxFuture;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Request, Response};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+
+mod mock;
+mod replay;
+
+pub use mock::{MockTransport, RecordedRequest};
+pub use replay::{Cassette, Interaction, Message, RecordingTransport, ReplayTransport};
+
+// Sends HTTP requests on behalf of a session.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>>;
+}
+
+// The default transport, backed by a hyper client using rustls.
+pub struct HyperTransport {
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl HyperTransport {
+    pub fn new(https_only: bool) -> HyperTransport {
+        let connector = HttpsConnectorBuilder::new().with_native_roots();
+        let connector = if https_only {
+            connector.https_only()
+        } else {
+            connector.https_or_http()
+        };
+        HyperTransport {
+            client: Client::builder().build(connector.enable_http1().build()),
+        }
+    }
+}
+
+impl Transport for HyperTransport {
+    fn send(&self, request: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        Box::pin(async move { Ok(self.client.request(request).await?) })
+    }
+}
@@ -0,0 +1,339 @@
+use super::Transport;
+use crate::error::Error;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::future::BoxFuture;
+use hyper::body::Bytes;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+static REDACTED: &str = "REDACTED";
+
+// Headers that carry credentials and are never written to a cassette.
+const REDACTED_HEADERS: [&str; 8] = [
+    "cookie",
+    "set-cookie",
+    "scnt",
+    "x-apple-session-token",
+    "x-apple-id-session-id",
+    "x-apple-twosv-trust-token",
+    "x-apple-oauth-state",
+    "authorization",
+];
+
+// JSON fields that carry credentials or personal data and are never
+// written to a cassette.
+const REDACTED_FIELDS: [&str; 15] = [
+    "accountName",
+    "password",
+    "m1",
+    "m2",
+    "dsWebAuthToken",
+    "trustToken",
+    "trustTokens",
+    "appleId",
+    "appleIdAliases",
+    "primaryEmail",
+    "fullName",
+    "firstName",
+    "lastName",
+    "numberWithDialCode",
+    "dsid",
+];
+
+// JSON objects whose `url` is a signed link to a file's contents.
+const SIGNED_URL_FIELDS: [&str; 2] = ["data_token", "package_token"];
+
+// A recorded HTTP message.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Message {
+    pub headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_base64: Option<String>,
+}
+
+// A request and the response it received.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Interaction {
+    pub method: String,
+    pub uri: String,
+    pub request: Message,
+    pub status: u16,
+    pub response: Message,
+}
+
+// A sequence of recorded interactions, stored as JSON.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load(path: &Path) -> Result<Cassette, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+// Replaces the strings and numbers in a value, keeping its shape so that a
+// replayed response still deserializes.
+fn redact_all(value: &mut Value) {
+    match value {
+        Value::String(_) => *value = Value::String(String::from(REDACTED)),
+        Value::Number(_) => *value = Value::from(0),
+        Value::Array(values) => values.iter_mut().for_each(redact_all),
+        Value::Object(map) => map.values_mut().for_each(redact_all),
+        _ => {}
+    }
+}
+
+fn redact_value(value: &mut Value, urls: &mut HashMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    redact_all(value);
+                    continue;
+                }
+                if SIGNED_URL_FIELDS.contains(&key.as_str()) {
+                    if let Some(url) = value.get_mut("url") {
+                        redact_url(url, urls);
+                    }
+                }
+                redact_value(value, urls);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| redact_value(value, urls)),
+        _ => {}
+    }
+}
+
+// Swaps a signed URL for a placeholder on the same host, remembering it so
+// that the request later made to it is recorded under the placeholder.
+fn redact_url(url: &mut Value, urls: &mut HashMap<String, String>) {
+    let signed = match url.as_str() {
+        Some(signed) => String::from(signed),
+        None => return,
+    };
+    let count = urls.len();
+    let placeholder = urls.entry(signed.clone()).or_insert_with(|| {
+        let origin = &signed[..signed.len() - path_and_query(&signed).len()];
+        format!("{}/{}/{}", origin, REDACTED, count + 1)
+    });
+    *url = Value::String(placeholder.clone());
+}
+
+// Replaces the values in a URI's query, which may identify the account or
+// the item asked for.
+fn redact_query(uri: &str) -> String {
+    match uri.split_once('?') {
+        Some((path, query)) => {
+            let query: Vec<String> = query
+                .split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((key, _)) => format!("{}={}", key, REDACTED),
+                    None => String::from(pair),
+                })
+                .collect();
+            format!("{}?{}", path, query.join("&"))
+        }
+        None => String::from(uri),
+    }
+}
+
+fn redact_uri(uri: &str, urls: &HashMap<String, String>) -> String {
+    match urls.get(uri) {
+        Some(placeholder) => placeholder.clone(),
+        None => redact_query(uri),
+    }
+}
+
+fn sanitize(headers: &HeaderMap, body: &Bytes, urls: &mut HashMap<String, String>) -> Message {
+    let headers = headers
+        .iter()
+        .map(|(key, value)| {
+            let value = if REDACTED_HEADERS.contains(&key.as_str()) {
+                String::from(REDACTED)
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (String::from(key.as_str()), value)
+        })
+        .collect();
+
+    let mut message = Message {
+        headers,
+        ..Message::default()
+    };
+    if body.is_empty() {
+        return message;
+    }
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact_value(&mut value, urls);
+            message.body = Some(value.to_string());
+        }
+        Err(_) => match std::str::from_utf8(body) {
+            Ok(text) => message.body = Some(String::from(text)),
+            Err(_) => message.body_base64 = Some(BASE64.encode(body)),
+        },
+    }
+    message
+}
+
+fn path_and_query(uri: &str) -> &str {
+    match uri.find("://") {
+        Some(index) => {
+            let rest = &uri[index + 3..];
+            rest.find('/').map_or("/", |start| &rest[start..])
+        }
+        None => uri,
+    }
+}
+
+// A transport that forwards requests to another transport and records
+// sanitized interactions, to be written out with `save`.
+#[derive(Clone)]
+pub struct RecordingTransport {
+    inner: Arc<dyn Transport>,
+    cassette: Arc<Mutex<Cassette>>,
+    // Signed URLs seen in responses, and the placeholders they were
+    // recorded as.
+    urls: Arc<Mutex<HashMap<String, String>>>,
+    path: PathBuf,
+}
+
+impl RecordingTransport {
+    pub fn new<T: Transport + 'static>(inner: T, path: &Path) -> RecordingTransport {
+        RecordingTransport {
+            inner: Arc::new(inner),
+            cassette: Arc::new(Mutex::new(Cassette::default())),
+            urls: Arc::new(Mutex::new(HashMap::new())),
+            path: path.to_path_buf(),
+        }
+    }
+
+    // Writes the interactions recorded so far to the cassette file.
+    pub fn save(&self) -> Result<(), Error> {
+        let cassette = self.cassette.lock().map_err(|_| Error::MutexError)?;
+        cassette.save(&self.path)
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send(&self, request: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let request_body = hyper::body::to_bytes(body).await?;
+            let method = parts.method.to_string();
+            let (uri, request_message) = {
+                let mut urls = self.urls.lock().map_err(|_| Error::MutexError)?;
+                (
+                    redact_uri(&parts.uri.to_string(), &urls),
+                    sanitize(&parts.headers, &request_body, &mut urls),
+                )
+            };
+
+            let response = self
+                .inner
+                .send(Request::from_parts(parts, Body::from(request_body)))
+                .await?;
+            let (parts, body) = response.into_parts();
+            let response_body = hyper::body::to_bytes(body).await?;
+            let response_message = {
+                let mut urls = self.urls.lock().map_err(|_| Error::MutexError)?;
+                sanitize(&parts.headers, &response_body, &mut urls)
+            };
+
+            self.cassette
+                .lock()
+                .map_err(|_| Error::MutexError)?
+                .interactions
+                .push(Interaction {
+                    method,
+                    uri,
+                    request: request_message,
+                    status: parts.status.as_u16(),
+                    response: response_message,
+                });
+
+            Ok(Response::from_parts(parts, Body::from(response_body)))
+        })
+    }
+}
+
+// A transport that answers requests from a cassette. Interactions are
+// matched in order on method, path and query parameter names, so the host
+// may differ from the one recorded, and query values are redacted.
+#[derive(Clone)]
+pub struct ReplayTransport {
+    interactions: Arc<Mutex<Vec<Option<Interaction>>>>,
+}
+
+impl ReplayTransport {
+    pub fn new(cassette: Cassette) -> ReplayTransport {
+        ReplayTransport {
+            interactions: Arc::new(Mutex::new(
+                cassette.interactions.into_iter().map(Some).collect(),
+            )),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<ReplayTransport, Error> {
+        Ok(ReplayTransport::new(Cassette::load(path)?))
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, request: Request<Body>) -> BoxFuture<'_, Result<Response<Body>, Error>> {
+        Box::pin(async move {
+            let method = request.method().to_string();
+            let uri = request.uri().to_string();
+
+            let interaction = {
+                let mut interactions = self.interactions.lock().map_err(|_| Error::MutexError)?;
+                interactions
+                    .iter_mut()
+                    .find(|interaction| {
+                        interaction.as_ref().is_some_and(|interaction| {
+                            interaction.method == method
+                                && path_and_query(&redact_query(&interaction.uri))
+                                    == path_and_query(&redact_query(&uri))
+                        })
+                    })
+                    .and_then(Option::take)
+            }
+            .ok_or_else(|| Error::UnexpectedRequest(format!("{} {}", method, uri)))?;
+
+            let body = match (interaction.response.body, interaction.response.body_base64) {
+                (Some(body), _) => Bytes::from(body),
+                (None, Some(body)) => Bytes::from(BASE64.decode(body)?),
+                (None, None) => Bytes::new(),
+            };
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = StatusCode::from_u16(interaction.status)?;
+            for (key, value) in interaction.response.headers {
+                response.headers_mut().append(
+                    HeaderName::from_bytes(key.as_bytes())?,
+                    HeaderValue::from_str(&value)?,
+                );
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use icloud::VerificationMode;
+use icloud::error::Error;
+
+#[tokio::test]
+async fn login_uses_srp() {
+    let server = common::server().await;
+    let mut client = server.client_builder().build().unwrap();
+    client.login("user@example.com", "password").await.unwrap();
+
+    let requests = server.requests();
+    assert!(requests.contains(&String::from("POST /appleauth/auth/signin/init")));
+    assert!(requests.contains(&String::from("POST /appleauth/auth/signin/complete")));
+    assert!(!requests.contains(&String::from("POST /appleauth/auth/signin")));
+    assert_eq!(client.account().await.unwrap().ds_info.dsid, "1234567890");
+}
+
+#[tokio::test]
+async fn login_rejects_wrong_password() {
+    let server = common::server().await;
+    let mut client = server.client_builder().build().unwrap();
+    let result = client.login("user@example.com", "wrong").await;
+    assert!(matches!(result, Err(Error::InvalidCredentials)));
+}
+
+#[tokio::test]
+async fn login_falls_back_to_plaintext_without_srp() {
+    let server = common::server().await;
+    server.with_failures(|failures| failures.disable_srp = true);
+    let mut client = server.client_builder().build().unwrap();
+    client.login("user@example.com", "password").await.unwrap();
+
+    assert!(server
+        .requests()
+        .contains(&String::from("POST /appleauth/auth/signin")));
+    assert!(client.drive().await.unwrap().root().await.is_ok());
+
+    let mut client = server.client_builder().build().unwrap();
+    let result = client.login("user@example.com", "wrong").await;
+    assert!(matches!(result, Err(Error::InvalidCredentials)));
+}
+
+#[tokio::test]
+async fn two_factor_with_trusted_device_code() {
+    let server = common::server().await;
+    server.require_2fa(true);
+    let mut client = server.client_builder().build().unwrap();
+
+    let result = client.login("user@example.com", "password").await;
+    assert!(matches!(result, Err(Error::Needs2FA)));
+    assert!(client.validate().await.unwrap().needs_2fa);
+
+    assert!(client.authenticate_2fa("000000").await.is_err());
+    client.authenticate_2fa("123456").await.unwrap();
+    assert!(!client.validate().await.unwrap().needs_2fa);
+    assert!(client.drive().await.unwrap().root().await.is_ok());
+
+    // The trust token saved with the session skips the second factor.
+    let data = client.save().await.unwrap();
+    let mut restored = server.client_builder().session_data(data).build().unwrap();
+    restored.login("user@example.com", "password").await.unwrap();
+}
+
+#[tokio::test]
+async fn two_factor_with_phone_code() {
+    let server = common::server().await;
+    server.require_2fa(true);
+    let mut client = server.client_builder().build().unwrap();
+    assert!(client.login("user@example.com", "password").await.is_err());
+
+    let phones = client.trusted_phone_numbers().await.unwrap();
+    assert_eq!(phones.len(), 1);
+    assert_eq!(phones[0].obfuscated_number, "(•••) •••-••00");
+
+    client
+        .request_phone_code(&phones[0], VerificationMode::Sms)
+        .await
+        .unwrap();
+    assert!(client
+        .authenticate_phone_2fa(&phones[0], VerificationMode::Sms, "000000")
+        .await
+        .is_err());
+    client
+        .authenticate_phone_2fa(&phones[0], VerificationMode::Sms, "123456")
+        .await
+        .unwrap();
+    assert!(client.drive().await.unwrap().root().await.is_ok());
+}
+
+#[tokio::test]
+async fn expired_session_is_renewed() {
+    let server = common::server().await;
+    let mut drive = common::drive(&server).await;
+    drive.root().await.unwrap();
+
+    server.expire_session();
+    let before = server.requests().len();
+    drive.root().await.unwrap();
+
+    let requests = server.requests()[before..].to_vec();
+    assert_eq!(
+        requests,
+        vec![
+            String::from("POST /drivews/retrieveItemDetailsInFolders"),
+            String::from("POST /setup/ws/1/accountLogin"),
+            String::from("POST /drivews/retrieveItemDetailsInFolders"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn revoked_tokens_need_a_new_login() {
+    let server = common::server().await;
+    let mut client = common::sign_in(&server).await;
+    let mut drive = client.drive().await.unwrap();
+
+    server.revoke_tokens();
+    assert!(drive.root().await.is_err());
+    assert!(!client.validate().await.unwrap().live);
+
+    client.login("user@example.com", "password").await.unwrap();
+    assert!(drive.root().await.is_ok());
+}
@@ -0,0 +1,234 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use icloud::backup::{Backup, DeletionPolicy};
+use icloud::testing::{MockServer, ROOT_ID};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+// The files beneath a directory, relative to it, leaving out the backup's
+// own state.
+fn local_files(root: &Path) -> Vec<PathBuf> {
+    fn collect(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.ends_with(".icloud-backup") {
+                continue;
+            }
+            if path.is_dir() {
+                collect(root, &path, files);
+            } else {
+                files.push(path.strip_prefix(root).unwrap().to_path_buf());
+            }
+        }
+    }
+    let mut files = Vec::new();
+    collect(root, root, &mut files);
+    files.sort();
+    files
+}
+
+fn downloads(server: &MockServer) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|request| request.ends_with("/download/by_id"))
+        .count()
+}
+
+#[tokio::test]
+async fn unchanged_files_are_skipped() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&documents, "report.pdf", b"%PDF");
+        drive.add_file(ROOT_ID, "notes.txt", b"notes");
+    });
+    let mut drive = common::drive(&server).await;
+    let dir = common::TempDir::new("backup-unchanged");
+    let backup = Backup::new(dir.path());
+
+    let report = backup.run(&mut drive).await.unwrap();
+    assert_eq!(report.downloaded.len(), 2);
+    assert_eq!(
+        local_files(dir.path()),
+        vec![PathBuf::from("Documents/report.pdf"), PathBuf::from("notes.txt")]
+    );
+    assert_eq!(std::fs::read(dir.path().join("Documents/report.pdf")).unwrap(), b"%PDF");
+
+    let before = downloads(&server);
+    let report = backup.run(&mut drive).await.unwrap();
+    assert!(report.downloaded.is_empty());
+    assert_eq!(report.unchanged, 2);
+    assert_eq!(downloads(&server), before);
+}
+
+#[tokio::test]
+async fn an_interrupted_run_resumes() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        for index in 0..40 {
+            drive.add_file(ROOT_ID, &format!("file {:02}.txt", index), b"contents");
+        }
+    });
+    let mut drive = common::drive(&server).await;
+    let dir = common::TempDir::new("backup-resume");
+    let backup = Backup::new(dir.path());
+    let manifest = dir.path().join(".icloud-backup").join("manifest.json");
+
+    // The run is stopped as soon as it first saves its manifest, part way
+    // through the files.
+    tokio::select! {
+        _ = backup.run(&mut drive) => panic!("the run was not interrupted"),
+        _ = async {
+            while !manifest.exists() {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        } => {}
+    }
+    let in_place = local_files(dir.path());
+    assert!(in_place.len() >= 25 && in_place.len() < 40, "{} files", in_place.len());
+
+    // Files already in place are kept, and only the rest are downloaded.
+    let report = backup.run(&mut drive).await.unwrap();
+    assert_eq!(report.unchanged, in_place.len());
+    assert_eq!(report.downloaded.len(), 40 - in_place.len());
+    assert!(report.downloaded.iter().all(|path| !in_place.contains(path)));
+    assert_eq!(local_files(dir.path()).len(), 40);
+
+    // Even without a manifest, files in place are recognised.
+    std::fs::remove_file(&manifest).unwrap();
+    let before = downloads(&server);
+    let report = backup.run(&mut drive).await.unwrap();
+    assert_eq!(report.unchanged, 40);
+    assert_eq!(downloads(&server), before);
+}
+
+#[tokio::test]
+async fn moves_and_renames_are_made_locally() {
+    let server = common::server().await;
+    let (documents, notes, archive) = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&documents, "report.pdf", b"%PDF");
+        let notes = drive.add_file(ROOT_ID, "notes.txt", b"notes");
+        (documents, notes, drive.add_folder(ROOT_ID, "Archive"))
+    });
+    let mut drive = common::drive(&server).await;
+    let dir = common::TempDir::new("backup-moves");
+    let backup = Backup::new(dir.path());
+    backup.run(&mut drive).await.unwrap();
+
+    server.with_drive(|drive| {
+        drive.get_mut(&documents).unwrap().name = String::from("Papers");
+        drive.touch(&documents);
+        drive.move_node(&notes, &archive);
+    });
+    let before = downloads(&server);
+    let report = backup.run(&mut drive).await.unwrap();
+    assert!(report.downloaded.is_empty());
+    assert_eq!(downloads(&server), before);
+    let mut moved = report.moved.clone();
+    moved.sort();
+    assert_eq!(
+        moved,
+        vec![
+            (PathBuf::from("Documents/report.pdf"), PathBuf::from("Papers/report.pdf")),
+            (PathBuf::from("notes.txt"), PathBuf::from("Archive/notes.txt")),
+        ]
+    );
+    assert_eq!(
+        local_files(dir.path()),
+        vec![PathBuf::from("Archive/notes.txt"), PathBuf::from("Papers/report.pdf")]
+    );
+    assert!(!dir.path().join("Documents").exists());
+}
+
+#[tokio::test]
+async fn deletion_policies() {
+    for name in ["keep", "archive", "delete"] {
+        let server = common::server().await;
+        let report_id = server.with_drive(|drive| {
+            let documents = drive.add_folder(ROOT_ID, "Documents");
+            drive.add_file(&documents, "notes.txt", b"notes");
+            drive.add_file(&documents, "report.pdf", b"%PDF")
+        });
+        let mut drive = common::drive(&server).await;
+        let dir = common::TempDir::new(&format!("backup-{}", name));
+        let archive = common::TempDir::new(&format!("backup-{}-archive", name));
+        let policy = match name {
+            "keep" => DeletionPolicy::Keep,
+            "archive" => DeletionPolicy::Archive(archive.path().to_path_buf()),
+            _ => DeletionPolicy::Delete,
+        };
+        let backup = Backup::new(dir.path()).with_deletions(policy.clone());
+        backup.run(&mut drive).await.unwrap();
+
+        server.with_drive(|drive| drive.remove(&report_id));
+        let report = backup.run(&mut drive).await.unwrap();
+        let kept = dir.path().join("Documents/report.pdf").exists();
+        let archived = local_files(archive.path());
+        match policy {
+            DeletionPolicy::Keep => {
+                assert!(report.removed.is_empty());
+                assert!(kept);
+                assert!(archived.is_empty());
+            }
+            DeletionPolicy::Archive(_) => {
+                assert_eq!(report.removed, vec![PathBuf::from("Documents/report.pdf")]);
+                assert!(!kept);
+                // Beneath a folder named for the time of the run.
+                assert_eq!(archived.len(), 1);
+                assert!(archived[0].ends_with("Documents/report.pdf"));
+                assert_eq!(archived[0].components().count(), 3);
+            }
+            DeletionPolicy::Delete => {
+                assert_eq!(report.removed, vec![PathBuf::from("Documents/report.pdf")]);
+                assert!(!kept);
+                assert!(archived.is_empty());
+            }
+        }
+        assert!(dir.path().join("Documents/notes.txt").exists());
+    }
+}
+
+#[tokio::test]
+async fn deletions_wait_for_a_complete_listing() {
+    let server = common::server().await;
+    let (report_id, photos) = server.with_drive(|drive| {
+        let report = drive.add_file(ROOT_ID, "report.pdf", b"%PDF");
+        let photos = drive.add_folder(ROOT_ID, "Photos");
+        drive.add_file(&photos, "cat.jpg", b"meow");
+        (report, photos)
+    });
+    let mut drive = common::drive(&server).await;
+    let dir = common::TempDir::new("backup-listing-errors");
+    let backup = Backup::new(dir.path()).with_deletions(DeletionPolicy::Delete);
+    backup.run(&mut drive).await.unwrap();
+
+    // While Photos can't be read, its contents would look deleted.
+    server.with_drive(|drive| {
+        drive.remove(&report_id);
+        drive
+            .get_mut(&photos)
+            .unwrap()
+            .overrides
+            .insert(String::from("directChildrenCount"), json!("many"));
+    });
+    let report = backup.run(&mut drive).await.unwrap();
+    assert_eq!(report.listing_errors.len(), 1);
+    assert!(report.removed.is_empty());
+    assert_eq!(
+        local_files(dir.path()),
+        vec![PathBuf::from("Photos/cat.jpg"), PathBuf::from("report.pdf")]
+    );
+
+    server.with_drive(|drive| {
+        drive.get_mut(&photos).unwrap().overrides.clear();
+        drive.touch(&photos);
+    });
+    let report = backup.run(&mut drive).await.unwrap();
+    assert!(report.listing_errors.is_empty());
+    assert_eq!(report.removed, vec![PathBuf::from("report.pdf")]);
+    assert_eq!(local_files(dir.path()), vec![PathBuf::from("Photos/cat.jpg")]);
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use icloud::drive::{Change, ChangeKind, ChangeToken};
+use icloud::testing::ROOT_ID;
+use std::path::PathBuf;
+
+// The kind and path of each change, in the order reported.
+fn summary(changes: &[Change]) -> Vec<(ChangeKind, PathBuf)> {
+    changes.iter().map(|change| (change.kind.clone(), change.path.clone())).collect()
+}
+
+#[tokio::test]
+async fn changes_since_a_token() {
+    let server = common::server().await;
+    let (documents, report) = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        let report = drive.add_file(&documents, "report.pdf", b"%PDF");
+        (documents, report)
+    });
+    let mut drive = common::drive(&server).await;
+
+    let (changes, token) = drive.changes_since(&ChangeToken::new()).await.unwrap();
+    assert_eq!(
+        summary(&changes),
+        vec![
+            (ChangeKind::Created, PathBuf::from("Documents")),
+            (ChangeKind::Created, PathBuf::from("Documents/report.pdf")),
+        ]
+    );
+    assert!(token.issued().is_some());
+    let (changes, token) = drive.changes_since(&token).await.unwrap();
+    assert!(changes.is_empty());
+
+    let (notes, archive) = server.with_drive(|drive| {
+        let notes = drive.add_file(&documents, "notes.txt", b"one");
+        (notes, drive.add_folder(ROOT_ID, "Archive"))
+    });
+    let (changes, token) = drive.changes_since(&token).await.unwrap();
+    assert_eq!(
+        summary(&changes),
+        vec![
+            (ChangeKind::Created, PathBuf::from("Archive")),
+            (ChangeKind::Created, PathBuf::from("Documents/notes.txt")),
+        ]
+    );
+
+    server.with_drive(|drive| {
+        drive.get_mut(&notes).unwrap().contents = b"two, longer".to_vec();
+        drive.touch(&notes);
+        drive.get_mut(&documents).unwrap().name = String::from("Docs");
+        drive.touch(&documents);
+    });
+    let (changes, token) = drive.changes_since(&token).await.unwrap();
+    assert_eq!(
+        summary(&changes),
+        vec![
+            (
+                ChangeKind::Renamed { from: String::from("Documents") },
+                PathBuf::from("Docs"),
+            ),
+            (ChangeKind::Modified, PathBuf::from("Docs/notes.txt")),
+        ]
+    );
+
+    server.with_drive(|drive| {
+        drive.move_node(&report, &archive);
+        drive.remove(&notes);
+    });
+    let (changes, token) = drive.changes_since(&token).await.unwrap();
+    assert_eq!(
+        summary(&changes),
+        vec![
+            (
+                ChangeKind::Moved { from: PathBuf::from("Docs/report.pdf") },
+                PathBuf::from("Archive/report.pdf"),
+            ),
+            (ChangeKind::Deleted, PathBuf::from("Docs/notes.txt")),
+        ]
+    );
+    assert!(changes[1].node.is_none());
+    assert_eq!(changes[1].id, notes);
+
+    // Moved and renamed at once is reported as a move, then a rename.
+    server.with_drive(|drive| {
+        drive.move_node(&report, &documents);
+        drive.get_mut(&report).unwrap().name = String::from("summary");
+        drive.touch(&report);
+    });
+    let (changes, _) = drive.changes_since(&token).await.unwrap();
+    assert_eq!(
+        summary(&changes),
+        vec![
+            (
+                ChangeKind::Moved { from: PathBuf::from("Archive/report.pdf") },
+                PathBuf::from("Docs/summary.pdf"),
+            ),
+            (
+                ChangeKind::Renamed { from: String::from("report.pdf") },
+                PathBuf::from("Docs/summary.pdf"),
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn a_saved_token_resumes() {
+    let server = common::server().await;
+    let documents = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&documents, "report.pdf", b"%PDF");
+        documents
+    });
+    let mut drive = common::drive(&server).await;
+    let (_, token) = drive.changes_since(&ChangeToken::new()).await.unwrap();
+    let issued = token.issued();
+    let saved = serde_json::to_string(&token).unwrap();
+
+    // A new client picks up where the saved token left off.
+    server.with_drive(|drive| drive.add_file(&documents, "notes.txt", b"notes"));
+    let token: ChangeToken = serde_json::from_str(&saved).unwrap();
+    assert_eq!(token.issued(), issued);
+    let mut drive = common::drive(&server).await;
+    let (changes, _) = drive.changes_since(&token).await.unwrap();
+    assert_eq!(
+        summary(&changes),
+        vec![(ChangeKind::Created, PathBuf::from("Documents/notes.txt"))]
+    );
+}
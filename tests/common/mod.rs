@@ -0,0 +1,85 @@
+// Helpers shared by the integration tests, not all of which use each one.
+#![allow(dead_code)]
+
+use icloud::drive::{DriveNode, DriveService, File, Folder};
+use icloud::testing::{MockAccount, MockServer};
+use icloud::Client;
+use std::path::{Path, PathBuf};
+
+pub async fn server() -> MockServer {
+    MockServer::start(MockAccount::default()).await.unwrap()
+}
+
+// A client signed in to `server` as its default account.
+pub async fn sign_in(server: &MockServer) -> Client {
+    let mut client = server.client_builder().build().unwrap();
+    client.login("user@example.com", "password").await.unwrap();
+    client
+}
+
+pub async fn drive(server: &MockServer) -> DriveService {
+    sign_in(server).await.drive().await.unwrap()
+}
+
+pub fn names(folder: &Folder) -> Vec<String> {
+    let mut names: Vec<String> = folder.iter().map(DriveNode::full_name).collect();
+    names.sort();
+    names
+}
+
+pub fn child(folder: &Folder, name: &str) -> DriveNode {
+    folder
+        .iter()
+        .find(|node| node.full_name() == name)
+        .cloned()
+        .unwrap_or_else(|| panic!("no {} in {}", name, folder.name))
+}
+
+pub fn folder(node: DriveNode) -> Folder {
+    match node {
+        DriveNode::Folder(folder) => folder,
+        node => panic!("{} is not a folder", node.name()),
+    }
+}
+
+pub fn file(folder: &Folder, name: &str) -> File {
+    match child(folder, name) {
+        DriveNode::File(file) => file,
+        _ => panic!("{} is not a file", name),
+    }
+}
+
+pub async fn read(drive: &mut DriveService, file: &File) -> Vec<u8> {
+    use futures::StreamExt;
+
+    let mut download = drive.download(file).await.unwrap();
+    let mut contents = Vec::new();
+    while let Some(chunk) = download.next().await {
+        contents.extend_from_slice(&chunk.unwrap());
+    }
+    contents
+}
+
+// A directory removed when the test is done with it.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("icloud-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
@@ -0,0 +1,84 @@
+use hyper::Uri;
+use icloud::CookieJar;
+
+fn uri(uri: &str) -> Uri {
+    uri.parse().unwrap()
+}
+
+#[test]
+fn host_only_cookies_stay_on_their_host() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1", &uri("https://www.icloud.com/setup"));
+
+    assert_eq!(jar.header(&uri("https://www.icloud.com/")), Some(String::from("a=1")));
+    assert_eq!(jar.header(&uri("https://sub.www.icloud.com/")), None);
+    assert_eq!(jar.header(&uri("https://icloud.com/")), None);
+}
+
+#[test]
+fn domain_cookies_reach_subdomains() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1; Domain=.icloud.com; Path=/", &uri("https://setup.icloud.com/"));
+
+    assert_eq!(jar.header(&uri("https://p01-drivews.icloud.com/")), Some(String::from("a=1")));
+    assert_eq!(jar.header(&uri("https://icloud.com/")), Some(String::from("a=1")));
+    assert_eq!(jar.header(&uri("https://noticloud.com/")), None);
+}
+
+#[test]
+fn cookies_for_other_domains_are_rejected() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1; Domain=apple.com", &uri("https://setup.icloud.com/"));
+    assert_eq!(jar.iter().count(), 0);
+}
+
+#[test]
+fn paths_match_on_segments() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1; Path=/setup", &uri("https://www.icloud.com/"));
+    jar.store("b=2; Path=/", &uri("https://www.icloud.com/"));
+
+    assert_eq!(jar.header(&uri("https://www.icloud.com/setup/ws")), Some(String::from("a=1; b=2")));
+    assert_eq!(jar.header(&uri("https://www.icloud.com/setup")), Some(String::from("a=1; b=2")));
+    assert_eq!(jar.header(&uri("https://www.icloud.com/setupx")), Some(String::from("b=2")));
+
+    // Without a Path, the default is the directory of the request path.
+    let mut jar = CookieJar::new();
+    jar.store("c=3", &uri("https://www.icloud.com/appleauth/auth/signin"));
+    assert_eq!(jar.header(&uri("https://www.icloud.com/appleauth/auth/2sv")), Some(String::from("c=3")));
+    assert_eq!(jar.header(&uri("https://www.icloud.com/appleauth")), None);
+}
+
+#[test]
+fn secure_cookies_need_https() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1; Secure", &uri("https://www.icloud.com/"));
+    assert_eq!(jar.header(&uri("http://www.icloud.com/")), None);
+    assert_eq!(jar.header(&uri("https://www.icloud.com/")), Some(String::from("a=1")));
+}
+
+#[test]
+fn expired_cookies_are_dropped() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT", &uri("https://www.icloud.com/"));
+    assert_eq!(jar.iter().count(), 0);
+
+    jar.store("b=2; Max-Age=3600", &uri("https://www.icloud.com/"));
+    jar.store("c=3; Max-Age=3600; Expires=Wed, 21 Oct 2015 07:28:00 GMT", &uri("https://www.icloud.com/"));
+    assert_eq!(jar.header(&uri("https://www.icloud.com/")), Some(String::from("b=2; c=3")));
+
+    // A cookie set again with Max-Age=0 is deleted.
+    jar.store("b=2; Max-Age=0", &uri("https://www.icloud.com/"));
+    assert_eq!(jar.header(&uri("https://www.icloud.com/")), Some(String::from("c=3")));
+}
+
+#[test]
+fn cookies_are_replaced_by_name_domain_and_path() {
+    let mut jar = CookieJar::new();
+    jar.store("a=1; Path=/", &uri("https://www.icloud.com/"));
+    jar.store("a=2; Path=/", &uri("https://www.icloud.com/"));
+    jar.store("a=3; Path=/setup", &uri("https://www.icloud.com/"));
+
+    assert_eq!(jar.iter().count(), 2);
+    assert_eq!(jar.header(&uri("https://www.icloud.com/setup")), Some(String::from("a=3; a=2")));
+}
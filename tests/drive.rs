@@ -0,0 +1,517 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use futures::io::Cursor;
+use futures::{stream, TryStreamExt};
+use hyper::StatusCode;
+use icloud::drive::{DriveNode, LoadState, MetadataCache, TrashItem, WalkOptions};
+use icloud::error::Error;
+use icloud::testing::{ROOT_ID, TRASH_ID};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn create_and_rename_folders() {
+    let server = common::server().await;
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+
+    let created = drive.create_folders(&root, &["Photos", "Work"]).await.unwrap();
+    assert_eq!(created.len(), 2);
+    let root = drive.root().await.unwrap();
+    assert_eq!(common::names(&root), vec!["Photos", "Work"]);
+
+    let work = drive.rename(&common::child(&root, "Work"), "Projects").await.unwrap();
+    assert_eq!(work.name(), "Projects");
+    assert_eq!(common::names(&drive.root().await.unwrap()), vec!["Photos", "Projects"]);
+}
+
+#[tokio::test]
+async fn refused_changes_name_their_items() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        drive.add_folder(ROOT_ID, "Photos");
+        drive.add_folder(ROOT_ID, "Work");
+    });
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+    let photos = common::child(&root, "Photos");
+    let work = common::child(&root, "Work");
+
+    server.fail_next(StatusCode::CONFLICT);
+    match drive.rename(&photos, "Pictures").await {
+        Err(Error::Conflict(id)) => assert_eq!(id, *photos.id()),
+        result => panic!("expected a conflict, got {:?}", result.map(|node| node.full_name())),
+    }
+
+    server.fail_next(StatusCode::PRECONDITION_FAILED);
+    match drive.move_items(&[&photos, &work], &root).await {
+        Err(Error::Conflict(ids)) => assert_eq!(ids, format!("{}, {}", photos.id(), work.id())),
+        result => panic!("expected a conflict, got {:?}", result.map(|nodes| nodes.len())),
+    }
+
+    server.fail_next(StatusCode::CONFLICT);
+    match drive.create_folders(&root, &["New"]).await {
+        Err(Error::Conflict(id)) => assert_eq!(id, root.id),
+        result => panic!("expected a conflict, got {:?}", result.map(|nodes| nodes.len())),
+    }
+}
+
+#[tokio::test]
+async fn move_into_a_folder() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        drive.add_folder(ROOT_ID, "Photos");
+        drive.add_folder(ROOT_ID, "Projects");
+    });
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+    let projects = common::folder(common::child(&root, "Projects"));
+
+    let moved = drive
+        .move_items(&[&common::child(&root, "Photos")], &projects)
+        .await
+        .unwrap();
+    assert!(moved[0].is_ok());
+    assert_eq!(common::names(&drive.root().await.unwrap()), vec!["Projects"]);
+    let projects = common::folder(drive.get_node(&projects.id).await.unwrap());
+    assert_eq!(common::names(&projects), vec!["Photos"]);
+}
+
+#[tokio::test]
+async fn lookup_by_path() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        let projects = drive.add_folder(ROOT_ID, "Projects");
+        let photos = drive.add_folder(&projects, "Photos");
+        drive.add_file(&photos, "cat.jpg", b"meow");
+    });
+    let mut drive = common::drive(&server).await;
+
+    let photos = drive.get_by_path("/projects/PHOTOS").await.unwrap();
+    assert_eq!(drive.path_of(&photos).await.unwrap(), "/Projects/Photos");
+    let cat = drive.get_by_path("/Projects/Photos/cat.jpg").await.unwrap();
+    assert_eq!(cat.full_name(), "cat.jpg");
+    assert_eq!(drive.parent(&cat).await.unwrap().unwrap().id, *photos.id());
+    assert!(matches!(
+        drive.get_by_path("/Projects/dog.jpg").await,
+        Err(Error::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn cached_paths_follow_moves_and_renames() {
+    let server = common::server().await;
+    let (a, b) = server.with_drive(|drive| {
+        let a = drive.add_folder(ROOT_ID, "A");
+        drive.add_file(&a, "x.txt", b"x");
+        (a, drive.add_folder(ROOT_ID, "B"))
+    });
+    let mut drive = common::drive(&server).await;
+    let x = drive.get_by_path("/A/x.txt").await.unwrap();
+    assert_eq!(drive.path_of(&x).await.unwrap(), "/A/x.txt");
+
+    // Another client moves A under B.
+    server.with_drive(|drive| drive.move_node(&a, &b));
+    assert!(matches!(drive.get_by_path("/A/x.txt").await, Err(Error::NotFound(_))));
+    let x = drive.get_by_path("/B/A/x.txt").await.unwrap();
+    assert_eq!(drive.path_of(&x).await.unwrap(), "/B/A/x.txt");
+
+    // And then renames B, an ancestor of the cached folder.
+    server.with_drive(|drive| {
+        drive.get_mut(&b).unwrap().name = String::from("C");
+        drive.touch(&b);
+    });
+    assert!(matches!(drive.get_by_path("/B/A/x.txt").await, Err(Error::NotFound(_))));
+    assert_eq!(drive.path_of(&x).await.unwrap(), "/C/A/x.txt");
+    assert_eq!(drive.get_by_path("/C/A/x.txt").await.unwrap().id(), x.id());
+}
+
+#[tokio::test]
+async fn get_nodes_reports_each_id() {
+    let server = common::server().await;
+    let (documents, report) = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        let report = drive.add_file(&documents, "report.pdf", b"%PDF");
+        (documents, report)
+    });
+    let mut drive = common::drive(&server).await;
+    let missing = "FOLDER::com.apple.CloudDocs::missing";
+
+    let nodes = drive.get_nodes(&[&documents, missing, &report]).await.unwrap();
+    assert_eq!(nodes.len(), 3);
+    let folder = common::folder(nodes[0].as_ref().unwrap().clone());
+    assert_eq!(folder.state, LoadState::Complete);
+    assert_eq!(common::names(&folder), vec!["report.pdf"]);
+    assert!(matches!(&nodes[1], Err(Error::NotFound(id)) if id == missing));
+    assert_eq!(nodes[2].as_ref().unwrap().full_name(), "report.pdf");
+
+    // With partialData, folders come without their children.
+    let nodes = drive.get_nodes_partial(&[&documents, missing]).await.unwrap();
+    let folder = common::folder(nodes[0].as_ref().unwrap().clone());
+    assert_eq!(folder.state, LoadState::Partial);
+    assert!(folder.items.is_empty());
+    assert_eq!(folder.direct_children_count, Some(1));
+    assert!(matches!(&nodes[1], Err(Error::NotFound(_))));
+
+    // Large batches are split to fit the server's limit.
+    let ids: Vec<&str> = std::iter::repeat_n(report.as_str(), 150).collect();
+    let before = server.requests().len();
+    let nodes = drive.get_nodes_partial(&ids).await.unwrap();
+    assert_eq!(nodes.len(), 150);
+    assert!(nodes.iter().all(Result::is_ok));
+    let batches = server.requests()[before..]
+        .iter()
+        .filter(|request| request.ends_with("/retrieveItemDetailsInFolders"))
+        .count();
+    assert_eq!(batches, 2);
+}
+
+#[tokio::test]
+async fn malformed_children_do_not_hide_their_siblings() {
+    let server = common::server().await;
+    let documents = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&documents, "a.txt", b"a");
+        let broken = drive.add_file(&documents, "b.txt", b"b");
+        drive.add_file(&documents, "c.txt", b"c");
+        drive.get_mut(&broken).unwrap().overrides.insert(String::from("size"), json!("big"));
+        documents
+    });
+    let mut drive = common::drive(&server).await;
+
+    let documents = common::folder(drive.get_node(&documents).await.unwrap());
+    assert_eq!(documents.items.len(), 3);
+    let broken: Vec<&DriveNode> = documents.iter().filter(|node| node.read_error().is_some()).collect();
+    assert_eq!(broken.len(), 1);
+    assert!(matches!(broken[0], DriveNode::Unknown(node) if node.kind == "FILE" && node.name == "b"));
+    assert_eq!(
+        broken[0].read_error().unwrap(),
+        "Invalid drive item field items[1].size: invalid type: string \"big\", expected u64"
+    );
+    let readable: Vec<String> = documents
+        .iter()
+        .filter_map(|node| match node {
+            DriveNode::File(file) => Some(file.full_name()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(readable, vec!["a.txt", "c.txt"]);
+}
+
+#[tokio::test]
+async fn documents_in_app_libraries() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        let pages = drive.add_app_library("com.apple.Pages", "Pages");
+        let doc = drive.add_file(&pages, "doc.pages", b"zip archive");
+        drive.get_mut(&doc).unwrap().package = true;
+        drive.add_app_library("com.apple.Numbers", "Numbers");
+    });
+    let mut drive = common::drive(&server).await;
+
+    let libraries = drive.app_libraries().await.unwrap();
+    let mut bundles: Vec<&str> = libraries.iter().map(|library| library.bundle_id.as_str()).collect();
+    bundles.sort();
+    assert_eq!(bundles, vec!["com.apple.Numbers", "com.apple.Pages"]);
+    let pages = libraries.iter().find(|library| library.folder.name == "Pages").unwrap();
+
+    let doc = drive.get_by_path("/Pages/doc.pages").await.unwrap();
+    assert_eq!(drive.parent(&doc).await.unwrap().unwrap().id, pages.folder.id);
+    assert_eq!(drive.path_of(&doc).await.unwrap(), "/Pages/doc.pages");
+    let file = match doc {
+        DriveNode::File(file) => file,
+        node => panic!("{} is not a file", node.full_name()),
+    };
+    assert_eq!(file.zone, "com.apple.Pages");
+    assert!(drive.download(&file).await.unwrap().is_package);
+    assert_eq!(common::read(&mut drive, &file).await, b"zip archive");
+}
+
+#[tokio::test]
+async fn folders_load_their_children_on_demand() {
+    let server = common::server().await;
+    let documents = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&documents, "a.txt", b"a");
+        documents
+    });
+    let mut drive = common::drive(&server).await;
+    let listings = || {
+        server
+            .requests()
+            .iter()
+            .filter(|request| request.ends_with("/retrieveItemDetailsInFolders"))
+            .count()
+    };
+
+    // A folder listed as a child doesn't carry its own children.
+    let root = drive.root().await.unwrap();
+    assert_eq!(root.state, LoadState::Complete);
+    let shallow = common::folder(common::child(&root, "Documents"));
+    assert_eq!(shallow.state, LoadState::Partial);
+    assert!(!shallow.is_complete());
+    assert_eq!(shallow.iter().state(), LoadState::Partial);
+    assert_eq!(shallow.iter().count(), 0);
+
+    let before = listings();
+    let children = drive.children(&shallow).await.unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(listings(), before + 1);
+
+    // A complete folder is listed without asking the server again.
+    let loaded = common::folder(drive.get_node(&documents).await.unwrap());
+    assert_eq!(loaded.iter().state(), LoadState::Complete);
+    let before = listings();
+    assert_eq!(drive.children(&loaded).await.unwrap().len(), 1);
+    assert_eq!(listings(), before);
+
+    // Another client adds a file, which a refresh picks up.
+    server.with_drive(|drive| drive.add_file(&documents, "b.txt", b"b"));
+    let refreshed = drive.refresh(&loaded).await.unwrap();
+    assert_eq!(common::names(&refreshed), vec!["a.txt", "b.txt"]);
+
+    // A listing shorter than the reported count was cut short.
+    server.with_drive(|drive| {
+        drive.get_mut(&documents).unwrap().overrides.insert(String::from("directChildrenCount"), json!(5));
+    });
+    let truncated = common::folder(drive.get_node(&documents).await.unwrap());
+    assert_eq!(truncated.items.len(), 2);
+    assert_eq!(truncated.state, LoadState::Partial);
+}
+
+#[tokio::test]
+async fn trash_and_restore() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        let folder = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&folder, "report.pdf", b"%PDF");
+    });
+    let mut drive = common::drive(&server).await;
+    let documents = common::child(&drive.root().await.unwrap(), "Documents");
+    let documents = common::folder(drive.get_node(documents.id()).await.unwrap());
+    let report = common::child(&documents, "report.pdf");
+
+    drive.delete(&report).await.unwrap();
+    let documents = common::folder(drive.get_node(&documents.id).await.unwrap());
+    assert!(common::names(&documents).is_empty());
+    let trash = drive.trash().await.unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!(trash[0].original_path.as_deref(), Some("Documents/report.pdf"));
+
+    let restored = drive.restore(&[&trash[0].node]).await.unwrap();
+    assert!(restored[0].is_ok());
+    assert!(drive.trash().await.unwrap().is_empty());
+    let documents = common::folder(drive.get_node(&documents.id).await.unwrap());
+    assert_eq!(common::names(&documents), vec!["report.pdf"]);
+}
+
+#[tokio::test]
+async fn unreadable_items_in_the_trash() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let id = drive.add_file(ROOT_ID, name, b"text");
+            drive.trash(&id);
+        }
+        let b = drive.children(TRASH_ID)[1].drivewsid.clone();
+        drive.get_mut(&b).unwrap().overrides.insert(String::from("size"), json!("big"));
+        // Listed under an id the server no longer knows, so it can't be
+        // deleted.
+        let c = drive.children(TRASH_ID)[2].drivewsid.clone();
+        let overrides = &mut drive.get_mut(&c).unwrap().overrides;
+        overrides.insert(String::from("size"), json!("big"));
+        overrides.insert(String::from("drivewsid"), json!("FILE::gone"));
+    });
+    let mut drive = common::drive(&server).await;
+
+    let trash = drive.trash().await.unwrap();
+    let mut names: Vec<String> = trash.iter().map(|item| item.node.full_name()).collect();
+    names.sort();
+    assert_eq!(names, vec!["a.txt", "b", "c"]);
+    let unreadable: Vec<&TrashItem> = trash.iter().filter(|item| item.node.read_error().is_some()).collect();
+    assert_eq!(unreadable.len(), 2);
+    assert!(unreadable.iter().all(|item| item.original_path.is_some()));
+
+    let emptied = drive.empty_trash().await.unwrap();
+    assert_eq!(emptied.len(), 3);
+    let failed: Vec<&str> = emptied
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .map(|(item, _)| item.node.id().as_str())
+        .collect();
+    assert_eq!(failed, vec!["FILE::gone"]);
+    assert_eq!(drive.trash().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn upload_and_download() {
+    let server = common::server().await;
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+
+    let contents = vec![7u8; 200 * 1024];
+    let file = drive
+        .upload(&root, "data.bin", Cursor::new(contents.clone()), contents.len() as u64)
+        .await
+        .unwrap();
+    assert_eq!(file.full_name(), "data.bin");
+    assert_eq!(file.size, contents.len() as u64);
+    assert_eq!(common::read(&mut drive, &file).await, contents);
+
+    // A file whose name differs only in case is updated in place.
+    let updated = drive
+        .upload(&root, "DATA.BIN", Cursor::new(b"new".to_vec()), 3)
+        .await
+        .unwrap();
+    assert_eq!(updated.id, file.id);
+    assert_eq!(common::read(&mut drive, &updated).await, b"new");
+    assert_eq!(common::names(&drive.root().await.unwrap()), vec!["data.bin"]);
+}
+
+#[tokio::test]
+async fn upload_over_a_changed_file_conflicts() {
+    let server = Arc::new(common::server().await);
+    server.with_drive(|drive| drive.add_file(ROOT_ID, "notes.txt", b"old"));
+    let mut client = common::sign_in(&server).await;
+    let mut drive = client.drive().await.unwrap();
+    let mut other = client.drive().await.unwrap();
+    let root = drive.root().await.unwrap();
+    let file = common::file(&root, "notes.txt");
+
+    // Someone else changes the file while the new contents are sent. The
+    // session is not held meanwhile, so other requests still go through.
+    let editor = server.clone();
+    let source = Box::pin(stream::once(async move {
+        editor.with_drive(|drive| drive.touch(&file.id));
+        other.root().await.unwrap();
+        Ok::<_, std::io::Error>(b"new".to_vec())
+    }))
+    .into_async_read();
+    match drive.upload(&root, "notes.txt", source, 3).await {
+        Err(Error::Conflict(id)) => assert_eq!(id, common::file(&root, "notes.txt").id),
+        result => panic!("expected a conflict, got {:?}", result.map(|file| file.id)),
+    }
+    let root = drive.root().await.unwrap();
+    assert_eq!(common::read(&mut drive, &common::file(&root, "notes.txt")).await, b"old");
+}
+
+#[tokio::test]
+async fn download_a_package() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        let id = drive.add_file(ROOT_ID, "Report.pages", b"zip archive");
+        drive.get_mut(&id).unwrap().package = true;
+        drive.add_file(ROOT_ID, "notes.txt", b"plain");
+    });
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+
+    let package = drive.download(&common::file(&root, "Report.pages")).await.unwrap();
+    assert!(package.is_package);
+    assert_eq!(package.size, Some(11));
+    let contents: Vec<u8> = package
+        .try_fold(Vec::new(), |mut contents, chunk| async move {
+            contents.extend_from_slice(&chunk);
+            Ok(contents)
+        })
+        .await
+        .unwrap();
+    assert_eq!(contents, b"zip archive");
+
+    let plain = drive.download(&common::file(&root, "notes.txt")).await.unwrap();
+    assert!(!plain.is_package);
+}
+
+#[tokio::test]
+async fn copy_by_transfer_never_overwrites() {
+    let server = common::server().await;
+    server.with_failures(|failures| failures.disable_copy = true);
+    let backup = server.with_drive(|drive| {
+        drive.add_file(ROOT_ID, "a.txt", b"original");
+        let backup = drive.add_folder(ROOT_ID, "Backup");
+        drive.add_file(&backup, "A.txt", b"other");
+        backup
+    });
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+    let file = common::file(&root, "a.txt");
+    let backup = common::folder(drive.get_node(&backup).await.unwrap());
+
+    assert_eq!(drive.copy(&file, &backup).await.unwrap().full_name(), "a copy.txt");
+    assert_eq!(drive.copy(&file, &backup).await.unwrap().full_name(), "a copy 2.txt");
+    assert_eq!(drive.copy(&file, &root).await.unwrap().full_name(), "a copy.txt");
+
+    let backup = common::folder(drive.get_node(&backup.id).await.unwrap());
+    assert_eq!(common::read(&mut drive, &common::file(&backup, "A.txt")).await, b"other");
+    assert_eq!(common::read(&mut drive, &common::file(&backup, "a copy 2.txt")).await, b"original");
+}
+
+#[tokio::test]
+async fn cached_listings_follow_changes() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        drive.add_folder(ROOT_ID, "Documents");
+    });
+    let mut drive = common::drive(&server).await.with_cache(MetadataCache::in_memory());
+
+    let documents = common::child(&drive.root().await.unwrap(), "Documents");
+    drive.rename(&documents, "Renamed").await.unwrap();
+    assert_eq!(common::names(&drive.root().await.unwrap()), vec!["Renamed"]);
+}
+
+#[tokio::test]
+async fn cached_listings_follow_restores_and_moves() {
+    let server = common::server().await;
+    let (documents, archive) = server.with_drive(|drive| {
+        let documents = drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(&documents, "report.pdf", b"%PDF");
+        (documents, drive.add_folder(ROOT_ID, "Archive"))
+    });
+    let mut drive = common::drive(&server).await.with_cache(MetadataCache::in_memory());
+
+    let listed = common::folder(drive.get_node(&documents).await.unwrap());
+    let report = common::child(&listed, "report.pdf");
+    drive.delete(&report).await.unwrap();
+    assert!(common::names(&common::folder(drive.get_node(&documents).await.unwrap())).is_empty());
+
+    // The restored file is back in the cached listing of its folder.
+    let trash = drive.trash().await.unwrap();
+    drive.restore(&[&trash[0].node]).await.unwrap()[0].as_ref().unwrap();
+    let listed = common::folder(drive.get_node(&documents).await.unwrap());
+    assert_eq!(common::names(&listed), vec!["report.pdf"]);
+
+    // Both folders a moved file passes between are listed afresh.
+    let archived = common::folder(drive.get_node(&archive).await.unwrap());
+    assert!(common::names(&archived).is_empty());
+    let report = common::child(&listed, "report.pdf");
+    drive.move_items(&[&report], &archived).await.unwrap()[0].as_ref().unwrap();
+    assert!(common::names(&common::folder(drive.get_node(&documents).await.unwrap())).is_empty());
+    let archived = common::folder(drive.get_node(&archive).await.unwrap());
+    assert_eq!(common::names(&archived), vec!["report.pdf"]);
+}
+
+#[tokio::test]
+async fn walk_lists_the_tree() {
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        for index in 0..150 {
+            let folder = drive.add_folder(ROOT_ID, &format!("folder {}", index));
+            drive.add_file(&folder, "file.txt", b"x");
+        }
+    });
+    let mut drive = common::drive(&server).await;
+    let root = drive.root().await.unwrap();
+
+    let items: Vec<(PathBuf, DriveNode)> = drive
+        .walk(&root, WalkOptions::new().concurrency(4))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(items.len(), 300);
+    assert!(items
+        .iter()
+        .any(|(path, _)| path == &PathBuf::from("folder 42/file.txt")));
+}
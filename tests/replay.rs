@@ -0,0 +1,63 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use icloud::testing::ROOT_ID;
+use icloud::transport::{Cassette, HyperTransport, RecordingTransport, ReplayTransport};
+use icloud::error::Error;
+use icloud::{Client, Endpoints};
+
+// Signs in, lists the root and reads a file, returning its contents.
+async fn session(mut client: Client) -> Vec<u8> {
+    client.login("user@example.com", "password").await.unwrap();
+    let mut drive = client.drive().await.unwrap();
+    let root = drive.root().await.unwrap();
+    assert_eq!(common::names(&root), vec!["Documents", "notes.txt"]);
+    common::read(&mut drive, &common::file(&root, "notes.txt")).await
+}
+
+#[tokio::test]
+async fn recorded_session_replays_without_a_server() {
+    let dir = common::TempDir::new("replay");
+    let path = dir.path().join("cassette.json");
+
+    let server = common::server().await;
+    server.with_drive(|drive| {
+        drive.add_folder(ROOT_ID, "Documents");
+        drive.add_file(ROOT_ID, "notes.txt", b"hello");
+    });
+    let recorder = RecordingTransport::new(HyperTransport::new(false), &path);
+    let client = server.client_builder().transport(recorder.clone()).build().unwrap();
+    assert_eq!(session(client).await, b"hello");
+    recorder.save().unwrap();
+    drop(server);
+
+    // Credentials, personal data and signed URLs are not written out.
+    let recorded = std::fs::read_to_string(&path).unwrap();
+    for secret in ["password", "user@example.com", "1234567890", "Example User", "/content/", "X-APPLE-WEBAUTH-TOKEN="] {
+        assert!(!recorded.contains(secret), "cassette contains {}", secret);
+    }
+
+    let endpoints = Endpoints::custom(
+        "http://replay.invalid/appleauth/auth",
+        "http://replay.invalid/setup/ws/1",
+        "http://replay.invalid",
+    );
+    let client = Client::builder()
+        .endpoints(endpoints)
+        .https_only(false)
+        .transport(ReplayTransport::load(&path).unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(session(client).await, b"hello");
+}
+
+#[tokio::test]
+async fn replay_reports_unexpected_requests() {
+    let mut client = Client::builder()
+        .transport(ReplayTransport::new(Cassette::default()))
+        .build()
+        .unwrap();
+    let result = client.login("user@example.com", "password").await;
+    assert!(matches!(result, Err(Error::UnexpectedRequest(_))));
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use icloud::error::Error;
+use icloud::Service;
+
+#[tokio::test]
+async fn unavailable_drive_service_is_reported() {
+    let server = common::server().await;
+    server.with_failures(|failures| failures.unavailable_services.push(String::from("drivews")));
+    let mut client = common::sign_in(&server).await;
+
+    let services = client.services().await;
+    assert_eq!(services["drivews"].status.as_deref(), Some("unavailable"));
+    assert!(services["docws"].is_available());
+    assert!(matches!(
+        client.service(Service::Drive).await,
+        Err(Error::ServiceUnavailable(service)) if service == "drivews"
+    ));
+    assert!(matches!(
+        client.drive().await,
+        Err(Error::ServiceUnavailable(service)) if service == "drivews"
+    ));
+    assert!(matches!(
+        client.service(Service::Photos).await,
+        Err(Error::MissingService(service)) if service == "photos"
+    ));
+}
+
+#[tokio::test]
+async fn logout_clears_the_session_and_the_trust() {
+    let server = common::server().await;
+    server.require_2fa(true);
+    let mut client = server.client_builder().build().unwrap();
+    assert!(client.login("user@example.com", "password").await.is_err());
+    client.authenticate_2fa("123456").await.unwrap();
+    let trusted = client.save().await.unwrap();
+
+    client.logout().await.unwrap();
+    assert!(server
+        .requests()
+        .contains(&String::from("POST /setup/ws/1/logout")));
+    let data = client.save().await.unwrap();
+    assert_eq!(data.cookies().iter().count(), 0);
+    assert!(data.ds_info().is_none());
+    assert!(client.services().await.is_empty());
+    assert!(client.drive().await.is_err());
+    assert!(!client.validate().await.unwrap().live);
+
+    // With `trustBrowsers: false` the server forgets this browser, so even
+    // the trust token saved before logging out needs a second factor again.
+    let mut restored = server.client_builder().session_data(trusted).build().unwrap();
+    let result = restored.login("user@example.com", "password").await;
+    assert!(matches!(result, Err(Error::Needs2FA)));
+    let result = client.login("user@example.com", "password").await;
+    assert!(matches!(result, Err(Error::Needs2FA)));
+}
@@ -0,0 +1,179 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use chrono::Utc;
+use icloud::drive::{DriveService, Folder};
+use icloud::sync::{SyncAction, SyncEngine};
+use icloud::testing::{MockDrive, MockServer, ROOT_ID};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+type Tree = BTreeMap<String, Option<Vec<u8>>>;
+
+// The files and folders beneath a folder on the mock drive, by path, with
+// `None` for folders.
+fn remote_tree(server: &MockServer, id: &str) -> Tree {
+    fn collect(drive: &MockDrive, id: &str, prefix: &str, tree: &mut Tree) {
+        for node in drive.children(id) {
+            let name = format!("{}{}", prefix, node.full_name());
+            if node.kind.is_folder() {
+                tree.insert(name.clone(), None);
+                collect(drive, &node.drivewsid, &format!("{}/", name), tree);
+            } else {
+                tree.insert(name, Some(node.contents.clone()));
+            }
+        }
+    }
+    server.with_drive(|drive| {
+        let mut tree = BTreeMap::new();
+        collect(drive, id, "", &mut tree);
+        tree
+    })
+}
+
+// The same for a local directory, leaving out the sync state.
+fn local_tree(root: &Path) -> Tree {
+    fn collect(dir: &Path, prefix: &str, tree: &mut Tree) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if name == ".icloud-sync" {
+                continue;
+            }
+            if entry.path().is_dir() {
+                tree.insert(name.clone(), None);
+                collect(&entry.path(), &format!("{}/", name), tree);
+            } else {
+                tree.insert(name, Some(std::fs::read(entry.path()).unwrap()));
+            }
+        }
+    }
+    let mut tree = BTreeMap::new();
+    collect(root, "", &mut tree);
+    tree
+}
+
+fn find(server: &MockServer, path: &str) -> String {
+    server.with_drive(|drive| {
+        let mut id = String::from(ROOT_ID);
+        for name in path.split('/') {
+            id = drive
+                .children(&id)
+                .iter()
+                .find(|node| node.full_name() == name)
+                .map(|node| node.drivewsid.clone())
+                .unwrap_or_else(|| panic!("no {} on the drive", path));
+        }
+        id
+    })
+}
+
+fn edit_remote(server: &MockServer, path: &str, contents: &[u8]) {
+    let id = find(server, path);
+    server.with_drive(|drive| {
+        let node = drive.get_mut(&id).unwrap();
+        node.contents = contents.to_vec();
+        node.date_modified = Utc::now();
+        let parent_id = node.parent_id.clone().unwrap();
+        drive.touch(&id);
+        drive.touch(&parent_id);
+    });
+}
+
+fn rename_remote(server: &MockServer, path: &str, name: &str) {
+    let id = find(server, path);
+    server.with_drive(|drive| {
+        let node = drive.get_mut(&id).unwrap();
+        node.name = String::from(name);
+        let parent_id = node.parent_id.clone().unwrap();
+        drive.touch(&id);
+        drive.touch(&parent_id);
+    });
+}
+
+// Syncs, then checks both sides match and nothing is left to do.
+async fn sync(
+    engine: &SyncEngine,
+    drive: &mut DriveService,
+    server: &MockServer,
+    local: &Path,
+    folder: &Folder,
+) -> Vec<SyncAction> {
+    let report = engine.run(drive).await.unwrap();
+    assert!(report.failed.is_empty(), "failed: {:?}", report.failed);
+    assert_eq!(local_tree(local), remote_tree(server, &folder.id));
+    assert!(engine.plan(drive).await.unwrap().is_empty());
+    report.applied
+}
+
+#[tokio::test]
+async fn sync_round_trip() {
+    let dir = common::TempDir::new("sync");
+    let local = dir.path().join("local");
+    let server = common::server().await;
+    let folder_id = server.with_drive(|drive| {
+        let folder = drive.add_folder(ROOT_ID, "Sync");
+        drive.add_file(&folder, "a.txt", b"a");
+        let docs = drive.add_folder(&folder, "docs");
+        drive.add_file(&docs, "b.txt", b"b");
+        folder
+    });
+    std::fs::create_dir_all(&local).unwrap();
+    std::fs::write(local.join("c.txt"), "c").unwrap();
+
+    let mut drive = common::drive(&server).await;
+    let folder = drive.get_node(&folder_id).await.unwrap().into_folder().unwrap();
+    let engine = SyncEngine::new(&local, &folder);
+
+    // The first sync merges both sides.
+    let applied = sync(&engine, &mut drive, &server, &local, &folder).await;
+    assert!(applied.contains(&SyncAction::Upload { path: "c.txt".into() }));
+    assert!(applied.contains(&SyncAction::Download { path: "docs/b.txt".into() }));
+    assert_eq!(local_tree(&local).len(), 4);
+
+    // Edits on either side.
+    std::fs::write(local.join("a.txt"), "a, edited locally").unwrap();
+    edit_remote(&server, "Sync/docs/b.txt", b"b, edited remotely");
+    let applied = sync(&engine, &mut drive, &server, &local, &folder).await;
+    assert_eq!(
+        applied,
+        vec![
+            SyncAction::Upload { path: "a.txt".into() },
+            SyncAction::Download { path: "docs/b.txt".into() },
+        ]
+    );
+    assert_eq!(std::fs::read(local.join("docs/b.txt")).unwrap(), b"b, edited remotely");
+
+    // Renames on either side, of a file and of a folder.
+    std::fs::rename(local.join("c.txt"), local.join("d.txt")).unwrap();
+    rename_remote(&server, "Sync/docs", "papers");
+    let applied = sync(&engine, &mut drive, &server, &local, &folder).await;
+    assert_eq!(applied.len(), 2, "{:?}", applied);
+    assert!(local.join("papers/b.txt").exists());
+    assert!(remote_tree(&server, &folder.id).contains_key("d.txt"));
+
+    // Deletions on either side.
+    std::fs::remove_file(local.join("a.txt")).unwrap();
+    let d = find(&server, "Sync/d.txt");
+    server.with_drive(|drive| drive.trash(&d));
+    let applied = sync(&engine, &mut drive, &server, &local, &folder).await;
+    assert_eq!(applied.len(), 2, "{:?}", applied);
+    assert_eq!(
+        local_tree(&local).keys().cloned().collect::<Vec<_>>(),
+        vec!["papers", "papers/b.txt"]
+    );
+
+    // Edits to the same file on both sides keep both versions.
+    std::fs::write(local.join("papers/b.txt"), "b, local conflict").unwrap();
+    edit_remote(&server, "Sync/papers/b.txt", b"b, remote conflict");
+    let applied = sync(&engine, &mut drive, &server, &local, &folder).await;
+    assert!(matches!(applied.as_slice(), [SyncAction::Conflict { .. }]), "{:?}", applied);
+    let tree = local_tree(&local);
+    assert_eq!(tree["papers/b.txt"], Some(b"b, remote conflict".to_vec()));
+    let copy = tree
+        .iter()
+        .find(|(path, _)| path.contains("(conflicted copy"))
+        .expect("no conflicted copy");
+    assert_eq!(copy.1, &Some(b"b, local conflict".to_vec()));
+}